@@ -1,5 +1,6 @@
 use std::{
     any::{Any, TypeId},
+    rc::Rc,
     sync::Arc,
 };
 
@@ -7,6 +8,10 @@ pub trait DowncastArc {
     fn downcast_arc<CastType: 'static>(&self) -> Option<Arc<CastType>>;
 }
 
+pub trait DowncastRc {
+    fn downcast_rc<CastType: 'static>(&self) -> Option<Rc<CastType>>;
+}
+
 impl<T> DowncastArc for Arc<T>
 where
     T: ?Sized + Any + 'static,
@@ -24,6 +29,23 @@ where
     }
 }
 
+impl<T> DowncastRc for Rc<T>
+where
+    T: ?Sized + Any + 'static,
+{
+    fn downcast_rc<CastType: 'static>(&self) -> Option<Rc<CastType>> {
+        let rc_clone = self.clone();
+
+        if (*rc_clone).type_id() == TypeId::of::<CastType>() {
+            let ptr = Rc::into_raw(rc_clone).cast::<CastType>();
+
+            Some(unsafe { Rc::from_raw(ptr) })
+        } else {
+            None
+        }
+    }
+}
+
 pub trait AsAny {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
@@ -41,3 +63,23 @@ where
         self
     }
 }
+
+impl AsAny for dyn Any {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl AsAny for dyn Any + Send + Sync {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
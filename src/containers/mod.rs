@@ -1,4 +1,8 @@
 pub mod multi_type_dict;
 pub mod object_map_pool;
 pub mod object_pool;
+pub mod rc_multi_type_dict;
 pub mod sendable_multi_type_dict;
+pub mod type_map;
+#[cfg(feature = "serde")]
+pub mod type_registry;
@@ -0,0 +1,14 @@
+use super::type_map::{
+    RcLocal, TypeMap, TypeMapChange, TypeMapInsertResult, TypeMapItem, TypeMapIterator,
+    TypeMapSnapshot, TypeMapSubscription,
+};
+
+pub use super::type_map::MergePolicy;
+
+pub type RcMultiTypeDict = TypeMap<RcLocal>;
+pub type RcMultiTypeDictItem<ItemType> = TypeMapItem<RcLocal, ItemType>;
+pub type RcMultiTypeDictSnapshot = TypeMapSnapshot<RcLocal>;
+pub type RcMultiTypeDictChange<ItemType> = TypeMapChange<RcLocal, ItemType>;
+pub type RcMultiTypeDictSubscription = TypeMapSubscription<RcLocal>;
+pub type RcMultiTypeDictIterator<'a> = TypeMapIterator<'a, RcLocal>;
+pub type RcMultiTypeDictInsertResult<ItemType> = TypeMapInsertResult<RcLocal, ItemType>;
@@ -0,0 +1,1339 @@
+//! # Type Map
+//!
+//! A dict that stores at most one item per type. The implementation is shared by all flavours,
+//! the flavour decides which values can be stored and how they are shared:
+//! - [`Local`]: values are stored in an [`Arc`], the map itself is neither `Send` nor `Sync`
+//! - [`Sendable`]: values have to be `Send + Sync` and so is the map
+//! - [`RcLocal`]: values are stored in an [`Rc`], the map itself is neither `Send` nor `Sync`
+//!
+//! A [`Sendable`] map can be converted into a [`Local`] one.
+//!
+//! The storage is a plain [`BTreeMap`] behind a shared pointer and it is copied on write as a
+//! whole: the first modification of a map or snapshot whose storage is shared with another one
+//! clones every entry, which is O(n). Only the pointers of the items are cloned, never the items
+//! themselves. A map holds one entry per type, so it is expected to stay small.
+
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, btree_map::Iter},
+    fmt::{self, Debug},
+    ops::Deref,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    cast::{AsAny, DowncastArc, DowncastRc},
+    containers::object_pool::{ObjectPool, ObjectPoolIndex},
+    sync::{
+        primitives::{self, Condvar, Mutex},
+        types::{ArcMutex, arc_mutex_new},
+    },
+};
+
+// the type locks use the primitives, so that they can be checked by loom
+type ItemTypeLock = primitives::Arc<(Mutex<bool>, Condvar)>;
+type DebugFn = fn(&dyn Any, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+type TypeMapStorage<Bound> = <Bound as TypeMapBound>::Shared<
+    BTreeMap<TypeId, TypeMapItem<Bound, <Bound as TypeMapBound>::Dyn>>,
+>;
+type TypeMapCallbackEntry<Bound> = <Bound as TypeMapBound>::Shared<SubscribedCallback<Bound>>;
+type TypeMapCallbacks<Bound> =
+    <Bound as TypeMapBound>::Shared<Mutex<ObjectPool<TypeMapCallbackEntry<Bound>>>>;
+type TypeMapSlot<Bound> = <Bound as TypeMapBound>::Shared<
+    Mutex<Option<TypeMapItem<Bound, <Bound as TypeMapBound>::Dyn>>>,
+>;
+
+pub trait TypeMapBound: Sized + 'static {
+    type Dyn: ?Sized + Any + AsAny;
+    type Pointer<ItemType: ?Sized + 'static>: Clone + Deref<Target = ItemType>;
+    type Shared<T: 'static>: Clone + Deref<Target = T>;
+    type Callback: ?Sized + FnMut(&TypeMapChange<Self, Self::Dyn>);
+
+    fn new_shared<T: 'static>(value: T) -> Self::Shared<T>;
+
+    fn make_mut<T: Clone + 'static>(shared: &mut Self::Shared<T>) -> &mut T;
+
+    fn downcast<CastType: 'static>(
+        pointer: &Self::Pointer<Self::Dyn>,
+    ) -> Option<Self::Pointer<CastType>>;
+
+    fn wait_for_callback(
+        slot: TypeMapSlot<Self>,
+        notify: Self::Shared<Notify>,
+    ) -> Box<Self::Callback>;
+}
+
+pub trait TypeMapValue<Bound: TypeMapBound>: Any {
+    fn into_pointer(self) -> Bound::Pointer<Bound::Dyn>;
+}
+
+pub trait TypeMapCallback<Bound: TypeMapBound, ItemType: ?Sized + 'static>:
+    FnMut(&TypeMapChange<Bound, ItemType>) + 'static
+{
+    fn into_boxed(self) -> Box<Bound::Callback>;
+}
+
+pub struct Local;
+
+pub struct Sendable;
+
+pub struct RcLocal;
+
+pub struct TypeMapItem<Bound: TypeMapBound, ItemType: ?Sized + 'static> {
+    type_id: TypeId,
+    type_name: &'static str,
+    debug_fn: Option<DebugFn>,
+    item: Bound::Pointer<ItemType>,
+}
+
+impl<Bound: TypeMapBound, ItemType: ?Sized + 'static> Clone for TypeMapItem<Bound, ItemType> {
+    fn clone(&self) -> Self {
+        Self {
+            type_id: self.type_id,
+            type_name: self.type_name,
+            debug_fn: self.debug_fn,
+            item: self.item.clone(),
+        }
+    }
+}
+
+pub struct TypeMap<Bound: TypeMapBound> {
+    storage: TypeMapStorage<Bound>,
+    item_type_locks: ArcMutex<BTreeMap<TypeId, ItemTypeLock>>,
+    subscribers: Bound::Shared<Mutex<BTreeMap<TypeId, TypeMapCallbacks<Bound>>>>,
+}
+
+pub struct TypeMapSnapshot<Bound: TypeMapBound> {
+    storage: TypeMapStorage<Bound>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MergePolicy {
+    KeepExisting,
+    Override,
+}
+
+pub enum TypeMapChange<Bound: TypeMapBound, ItemType: ?Sized + 'static> {
+    Inserted {
+        new_item: TypeMapItem<Bound, ItemType>,
+    },
+    Replaced {
+        old_item: TypeMapItem<Bound, ItemType>,
+        new_item: TypeMapItem<Bound, ItemType>,
+    },
+    Removed {
+        old_item: TypeMapItem<Bound, ItemType>,
+    },
+}
+
+pub struct TypeMapSubscription<Bound: TypeMapBound> {
+    callback_index: ObjectPoolIndex,
+    callbacks: TypeMapCallbacks<Bound>,
+    entry: TypeMapCallbackEntry<Bound>,
+}
+
+struct SubscribedCallback<Bound: TypeMapBound> {
+    // cleared when the subscription is dropped, so that a notification which is already running
+    // skips the callback
+    subscribed: AtomicBool,
+    callback: Mutex<Box<Bound::Callback>>,
+}
+
+pub struct ItemTypeGuard {
+    item_type_locks: ArcMutex<BTreeMap<TypeId, ItemTypeLock>>,
+    type_id: TypeId,
+    // only None while the guard is dropped
+    lock: Option<ItemTypeLock>,
+}
+
+pub struct TypeMapIterator<'a, Bound: TypeMapBound> {
+    inner_iterator: Iter<'a, TypeId, TypeMapItem<Bound, Bound::Dyn>>,
+}
+
+pub struct TypeMapInsertResult<Bound: TypeMapBound, ItemType: ?Sized + 'static> {
+    pub new_item: TypeMapItem<Bound, ItemType>,
+    pub old_item: Option<TypeMapItem<Bound, ItemType>>,
+}
+
+impl<'a, Bound: TypeMapBound> Iterator for TypeMapIterator<'a, Bound> {
+    type Item = TypeMapItem<Bound, Bound::Dyn>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner_iterator.next().map(|value| value.1.clone())
+    }
+}
+
+impl<Bound: TypeMapBound> TypeMap<Bound> {
+    pub fn new() -> Self {
+        Self {
+            storage: Bound::new_shared(BTreeMap::new()),
+            item_type_locks: arc_mutex_new(BTreeMap::new()),
+            subscribers: Bound::new_shared(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn insert<ItemType>(&mut self, item: ItemType) -> TypeMapInsertResult<Bound, ItemType>
+    where
+        ItemType: TypeMapValue<Bound>,
+    {
+        let type_id = TypeId::of::<ItemType>();
+
+        Self::downcast_insert_result(self.insert_any(item, type_id))
+    }
+
+    pub fn insert_with_debug<ItemType>(
+        &mut self,
+        item: ItemType,
+    ) -> TypeMapInsertResult<Bound, ItemType>
+    where
+        ItemType: TypeMapValue<Bound> + Debug,
+    {
+        let new_item =
+            TypeMapItem::new(item, TypeId::of::<ItemType>(), Some(debug_any::<ItemType>));
+
+        Self::downcast_insert_result(self.insert_item(new_item))
+    }
+
+    pub fn insert_any(
+        &mut self,
+        item: impl TypeMapValue<Bound>,
+        type_id: TypeId,
+    ) -> TypeMapInsertResult<Bound, Bound::Dyn> {
+        self.insert_item(TypeMapItem::new(item, type_id, None))
+    }
+
+    fn insert_item(
+        &mut self,
+        new_item: TypeMapItem<Bound, Bound::Dyn>,
+    ) -> TypeMapInsertResult<Bound, Bound::Dyn> {
+        let type_id = new_item.type_id;
+
+        let old_item = Bound::make_mut(&mut self.storage).insert(type_id, new_item.clone());
+
+        self.notify_subscribers(match old_item.clone() {
+            Some(old_item) => TypeMapChange::Replaced {
+                old_item,
+                new_item: new_item.clone(),
+            },
+            None => TypeMapChange::Inserted {
+                new_item: new_item.clone(),
+            },
+        });
+
+        TypeMapInsertResult { new_item, old_item }
+    }
+
+    pub fn get_item_ref<ItemType>(&self) -> Option<TypeMapItem<Bound, ItemType>>
+    where
+        ItemType: Any,
+    {
+        let type_id = TypeId::of::<ItemType>();
+
+        self.get_item_ref_any(type_id)
+            .and_then(|item| item.downcast::<ItemType>())
+    }
+
+    pub fn get_or_insert_item_ref<ItemType>(
+        &mut self,
+        item_creator: impl FnOnce() -> ItemType,
+    ) -> TypeMapItem<Bound, ItemType>
+    where
+        ItemType: TypeMapValue<Bound>,
+    {
+        let _item_type_guard = self.lock_item_type::<ItemType>();
+
+        if let Some(item) = self.get_item_ref::<ItemType>() {
+            item
+        } else {
+            self.insert(item_creator()).new_item
+        }
+    }
+
+    pub fn get_item_ref_any(&self, type_id: TypeId) -> Option<TypeMapItem<Bound, Bound::Dyn>> {
+        self.storage.get(&type_id).cloned()
+    }
+
+    pub fn remove<ItemType>(&mut self) -> Option<Bound::Pointer<ItemType>>
+    where
+        ItemType: Any,
+    {
+        let type_id = TypeId::of::<ItemType>();
+
+        self.remove_by_type_id(type_id)
+            .and_then(|item| item.downcast::<ItemType>())
+            .map(|item| item.item)
+    }
+
+    pub fn remove_by_type_id(&mut self, type_id: TypeId) -> Option<TypeMapItem<Bound, Bound::Dyn>> {
+        let old_item = Bound::make_mut(&mut self.storage).remove(&type_id);
+
+        if let Some(old_item) = old_item.clone() {
+            self.notify_subscribers(TypeMapChange::Removed { old_item });
+        }
+
+        old_item
+    }
+
+    pub fn iter(&self) -> TypeMapIterator<'_, Bound> {
+        TypeMapIterator {
+            inner_iterator: self.storage.iter(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    pub fn merge_from(&mut self, other: &Self, policy: MergePolicy) {
+        for (type_id, item) in other.storage.iter() {
+            if policy == MergePolicy::KeepExisting && self.storage.contains_key(type_id) {
+                continue;
+            }
+
+            self.insert_item(item.clone());
+        }
+    }
+
+    /// Creating a snapshot is cheap, but the first modification of the map while the snapshot is
+    /// alive copies the whole storage, see the module docs. The items themselves are never copied.
+    pub fn snapshot(&self) -> TypeMapSnapshot<Bound> {
+        TypeMapSnapshot {
+            storage: self.storage.clone(),
+        }
+    }
+
+    pub fn subscribe<ItemType>(
+        &self,
+        f: impl TypeMapCallback<Bound, ItemType>,
+    ) -> TypeMapSubscription<Bound>
+    where
+        ItemType: Any,
+    {
+        self.subscribe_boxed(TypeId::of::<ItemType>(), f.into_boxed())
+    }
+
+    pub fn subscribe_any(
+        &self,
+        type_id: TypeId,
+        f: impl TypeMapCallback<Bound, Bound::Dyn>,
+    ) -> TypeMapSubscription<Bound> {
+        self.subscribe_boxed(type_id, f.into_boxed())
+    }
+
+    /// The returned future does not borrow the map, so it can be awaited while the map is being
+    /// modified elsewhere. It resolves with the item of the given type as soon as there is one.
+    pub fn wait_for<ItemType>(&self) -> impl Future<Output = TypeMapItem<Bound, ItemType>> + 'static
+    where
+        ItemType: TypeMapValue<Bound>,
+    {
+        let slot = Bound::new_shared(Mutex::new(self.get_item_ref_any(TypeId::of::<ItemType>())));
+        let notify = Bound::new_shared(Notify::new());
+
+        let subscription = self.subscribe_boxed(
+            TypeId::of::<ItemType>(),
+            Bound::wait_for_callback(slot.clone(), notify.clone()),
+        );
+
+        async move {
+            let _subscription = subscription;
+
+            loop {
+                let item = slot.lock().take();
+                if let Some(item) = item.and_then(|item| item.downcast::<ItemType>()) {
+                    break item;
+                }
+
+                notify.notified().await;
+            }
+        }
+    }
+
+    fn subscribe_boxed(
+        &self,
+        type_id: TypeId,
+        f: Box<Bound::Callback>,
+    ) -> TypeMapSubscription<Bound> {
+        let callbacks = self
+            .subscribers
+            .lock()
+            .entry(type_id)
+            .or_insert_with(|| Bound::new_shared(Mutex::new(ObjectPool::new())))
+            .clone();
+
+        let entry = Bound::new_shared(SubscribedCallback {
+            subscribed: AtomicBool::new(true),
+            callback: Mutex::new(f),
+        });
+        let callback_index = callbacks.lock().create_object(entry.clone());
+
+        TypeMapSubscription {
+            callback_index,
+            callbacks,
+            entry,
+        }
+    }
+
+    fn downcast_insert_result<ItemType>(
+        result: TypeMapInsertResult<Bound, Bound::Dyn>,
+    ) -> TypeMapInsertResult<Bound, ItemType>
+    where
+        ItemType: 'static,
+    {
+        if let Some(new_item) = result.new_item.downcast() {
+            if let Some(old_item) = result.old_item {
+                if let Some(old_item) = old_item.downcast() {
+                    TypeMapInsertResult {
+                        new_item,
+                        old_item: Some(old_item),
+                    }
+                } else {
+                    unreachable!();
+                }
+            } else {
+                TypeMapInsertResult {
+                    new_item,
+                    old_item: None,
+                }
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// The callbacks are cloned out of the subscriber lists before they are called, so a callback
+    /// can drop any subscription or `wait_for` future, including its own, without deadlocking.
+    fn notify_subscribers(&self, change: TypeMapChange<Bound, Bound::Dyn>) {
+        let callbacks = self.subscribers.lock().get(&change.type_id()).cloned();
+        let Some(callbacks) = callbacks else {
+            return;
+        };
+
+        let entries: Vec<_> = callbacks.lock().iter().cloned().collect();
+        for entry in entries {
+            if entry.subscribed.load(Ordering::Acquire) {
+                (entry.callback.lock())(&change);
+            }
+        }
+    }
+
+    fn lock_item_type<ItemType>(&self) -> ItemTypeGuard
+    where
+        ItemType: Any,
+    {
+        let type_id = TypeId::of::<ItemType>();
+        let mut item_type_locks = self.item_type_locks.lock();
+        let entry = item_type_locks
+            .entry(type_id)
+            .or_insert_with(|| primitives::Arc::new((Mutex::new(false), Condvar::new())));
+
+        let entry = entry.clone();
+
+        drop(item_type_locks);
+
+        let mut item_type_locked = entry.0.lock();
+        while *item_type_locked {
+            entry.1.wait(&mut item_type_locked);
+        }
+        *item_type_locked = true;
+
+        ItemTypeGuard {
+            item_type_locks: self.item_type_locks.clone(),
+            type_id,
+            lock: Some(entry.clone()),
+        }
+    }
+}
+
+impl<Bound: TypeMapBound> TypeMapSnapshot<Bound> {
+    pub fn get_item_ref<ItemType>(&self) -> Option<TypeMapItem<Bound, ItemType>>
+    where
+        ItemType: Any,
+    {
+        self.get_item_ref_any(TypeId::of::<ItemType>())
+            .and_then(|item| item.downcast::<ItemType>())
+    }
+
+    pub fn get_item_ref_any(&self, type_id: TypeId) -> Option<TypeMapItem<Bound, Bound::Dyn>> {
+        self.storage.get(&type_id).cloned()
+    }
+
+    /// Copies the whole storage, see the module docs.
+    pub fn with<ItemType>(&self, item: ItemType) -> Self
+    where
+        ItemType: TypeMapValue<Bound>,
+    {
+        let type_id = TypeId::of::<ItemType>();
+
+        let mut storage = self.storage.clone();
+        Bound::make_mut(&mut storage).insert(type_id, TypeMapItem::new(item, type_id, None));
+
+        Self { storage }
+    }
+
+    /// Copies the whole storage, see the module docs.
+    pub fn without<ItemType>(&self) -> Self
+    where
+        ItemType: Any,
+    {
+        let mut storage = self.storage.clone();
+        Bound::make_mut(&mut storage).remove(&TypeId::of::<ItemType>());
+
+        Self { storage }
+    }
+
+    pub fn iter(&self) -> TypeMapIterator<'_, Bound> {
+        TypeMapIterator {
+            inner_iterator: self.storage.iter(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
+
+impl<Bound: TypeMapBound> TypeMapItem<Bound, Bound::Dyn> {
+    pub fn downcast<CastType: 'static>(&self) -> Option<TypeMapItem<Bound, CastType>> {
+        Bound::downcast::<CastType>(&self.item).map(|item| TypeMapItem {
+            type_id: self.type_id,
+            type_name: self.type_name,
+            debug_fn: self.debug_fn,
+            item,
+        })
+    }
+
+    fn new(item: impl TypeMapValue<Bound>, type_id: TypeId, debug_fn: Option<DebugFn>) -> Self {
+        Self {
+            type_id,
+            type_name: std::any::type_name_of_val(&item),
+            debug_fn,
+            item: item.into_pointer(),
+        }
+    }
+}
+
+impl<Bound: TypeMapBound> TypeMapChange<Bound, Bound::Dyn> {
+    pub fn downcast<CastType: 'static>(&self) -> Option<TypeMapChange<Bound, CastType>> {
+        Some(match self {
+            Self::Inserted { new_item } => TypeMapChange::Inserted {
+                new_item: new_item.downcast()?,
+            },
+            Self::Replaced { old_item, new_item } => TypeMapChange::Replaced {
+                old_item: old_item.downcast()?,
+                new_item: new_item.downcast()?,
+            },
+            Self::Removed { old_item } => TypeMapChange::Removed {
+                old_item: old_item.downcast()?,
+            },
+        })
+    }
+}
+
+impl<Bound: TypeMapBound, ItemType: ?Sized + 'static> TypeMapChange<Bound, ItemType> {
+    pub fn new_item(&self) -> Option<&TypeMapItem<Bound, ItemType>> {
+        match self {
+            Self::Inserted { new_item } | Self::Replaced { new_item, .. } => Some(new_item),
+            Self::Removed { .. } => None,
+        }
+    }
+
+    pub fn old_item(&self) -> Option<&TypeMapItem<Bound, ItemType>> {
+        match self {
+            Self::Replaced { old_item, .. } | Self::Removed { old_item } => Some(old_item),
+            Self::Inserted { .. } => None,
+        }
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        match self {
+            Self::Inserted { new_item } | Self::Replaced { new_item, .. } => new_item.type_id(),
+            Self::Removed { old_item } => old_item.type_id(),
+        }
+    }
+}
+
+impl<Bound: TypeMapBound, ItemType: ?Sized + 'static> TypeMapItem<Bound, ItemType> {
+    pub fn as_pointer_ref(&self) -> &Bound::Pointer<ItemType> {
+        &self.item
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl<ItemType: ?Sized + 'static> TypeMapItem<Local, ItemType> {
+    pub fn as_arc_ref(&self) -> &Arc<ItemType> {
+        &self.item
+    }
+}
+
+impl<ItemType: ?Sized + 'static> TypeMapItem<Sendable, ItemType> {
+    pub fn as_arc_ref(&self) -> &Arc<ItemType> {
+        &self.item
+    }
+}
+
+impl<ItemType: ?Sized + 'static> TypeMapItem<RcLocal, ItemType> {
+    pub fn as_rc_ref(&self) -> &Rc<ItemType> {
+        &self.item
+    }
+}
+
+impl<Bound: TypeMapBound, ItemType: ?Sized + AsAny + 'static> Debug
+    for TypeMapItem<Bound, ItemType>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.debug_fn {
+            Some(debug_fn) => debug_fn(AsAny::as_any(&*self.item), f),
+            None => f.debug_struct(self.type_name).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<Bound: TypeMapBound, ItemType: ?Sized + 'static> Deref for TypeMapItem<Bound, ItemType> {
+    type Target = ItemType;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_pointer_ref()
+    }
+}
+
+impl<Bound: TypeMapBound> Debug for TypeMap<Bound> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.storage.values().map(|item| (item.type_name(), item)))
+            .finish()
+    }
+}
+
+impl<Bound: TypeMapBound> Debug for TypeMapSnapshot<Bound> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.storage.values().map(|item| (item.type_name(), item)))
+            .finish()
+    }
+}
+
+impl<Bound: TypeMapBound> Clone for TypeMap<Bound> {
+    /// The clone shares the items with the original map, but not the subscriptions.
+    fn clone(&self) -> Self {
+        Self::from(self.snapshot())
+    }
+}
+
+impl<Bound: TypeMapBound> Clone for TypeMapSnapshot<Bound> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<Bound: TypeMapBound> From<TypeMapSnapshot<Bound>> for TypeMap<Bound> {
+    fn from(snapshot: TypeMapSnapshot<Bound>) -> Self {
+        Self {
+            storage: snapshot.storage,
+            item_type_locks: arc_mutex_new(BTreeMap::new()),
+            subscribers: Bound::new_shared(Mutex::new(BTreeMap::new())),
+        }
+    }
+}
+
+impl From<TypeMapItem<Sendable, dyn Any + Send + Sync>> for TypeMapItem<Local, dyn Any> {
+    fn from(item: TypeMapItem<Sendable, dyn Any + Send + Sync>) -> Self {
+        Self {
+            type_id: item.type_id,
+            type_name: item.type_name,
+            debug_fn: item.debug_fn,
+            item: item.item,
+        }
+    }
+}
+
+impl<ItemType: Any> From<TypeMapItem<Sendable, ItemType>> for TypeMapItem<Local, ItemType> {
+    fn from(item: TypeMapItem<Sendable, ItemType>) -> Self {
+        Self {
+            type_id: item.type_id,
+            type_name: item.type_name,
+            debug_fn: item.debug_fn,
+            item: item.item,
+        }
+    }
+}
+
+impl From<TypeMapSnapshot<Sendable>> for TypeMapSnapshot<Local> {
+    fn from(snapshot: TypeMapSnapshot<Sendable>) -> Self {
+        Self {
+            storage: Rc::new(
+                snapshot
+                    .storage
+                    .iter()
+                    .map(|(type_id, item)| (*type_id, item.clone().into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<TypeMap<Sendable>> for TypeMap<Local> {
+    fn from(map: TypeMap<Sendable>) -> Self {
+        Self::from(TypeMapSnapshot::<Local>::from(map.snapshot()))
+    }
+}
+
+impl<Bound: TypeMapBound> Default for TypeMap<Bound> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Bound: TypeMapBound> Drop for TypeMapSubscription<Bound> {
+    fn drop(&mut self) {
+        // a callback that is already running on another thread is not waited for
+        self.entry.subscribed.store(false, Ordering::Release);
+        self.callbacks
+            .lock()
+            .release_object(self.callback_index.invalidate());
+    }
+}
+
+impl Drop for ItemTypeGuard {
+    fn drop(&mut self) {
+        let Some(lock) = self.lock.take() else {
+            return;
+        };
+
+        let mut item_type_locks = self.item_type_locks.lock();
+        // check that only item_type_locks and self contains this lock
+        if primitives::Arc::strong_count(&lock) == 2 {
+            // nobody tries to lock the item type
+            item_type_locks.remove(&self.type_id);
+        } else {
+            // somebody tries to lock the item type
+            let mut item_type_locked = lock.0.lock();
+            *item_type_locked = false;
+            lock.1.notify_one();
+        }
+
+        // released before item_type_locks, otherwise the next guard of the type could still count
+        // it and never remove the lock
+        drop(lock);
+    }
+}
+
+impl TypeMapBound for Local {
+    type Dyn = dyn Any;
+    type Pointer<ItemType: ?Sized + 'static> = Arc<ItemType>;
+    type Shared<T: 'static> = Rc<T>;
+    type Callback = dyn FnMut(&TypeMapChange<Self, dyn Any>);
+
+    fn new_shared<T: 'static>(value: T) -> Self::Shared<T> {
+        Rc::new(value)
+    }
+
+    fn make_mut<T: Clone + 'static>(shared: &mut Self::Shared<T>) -> &mut T {
+        Rc::make_mut(shared)
+    }
+
+    fn downcast<CastType: 'static>(pointer: &Arc<dyn Any>) -> Option<Arc<CastType>> {
+        pointer.downcast_arc()
+    }
+
+    fn wait_for_callback(slot: TypeMapSlot<Self>, notify: Rc<Notify>) -> Box<Self::Callback> {
+        Box::new(move |change| {
+            *slot.lock() = change.new_item().cloned();
+            notify.notify_one();
+        })
+    }
+}
+
+impl TypeMapBound for Sendable {
+    type Dyn = dyn Any + Send + Sync;
+    type Pointer<ItemType: ?Sized + 'static> = Arc<ItemType>;
+    type Shared<T: 'static> = Arc<T>;
+    type Callback = dyn FnMut(&TypeMapChange<Self, dyn Any + Send + Sync>) + Send;
+
+    fn new_shared<T: 'static>(value: T) -> Self::Shared<T> {
+        Arc::new(value)
+    }
+
+    fn make_mut<T: Clone + 'static>(shared: &mut Self::Shared<T>) -> &mut T {
+        Arc::make_mut(shared)
+    }
+
+    fn downcast<CastType: 'static>(pointer: &Arc<dyn Any + Send + Sync>) -> Option<Arc<CastType>> {
+        pointer.downcast_arc()
+    }
+
+    fn wait_for_callback(slot: TypeMapSlot<Self>, notify: Arc<Notify>) -> Box<Self::Callback> {
+        Box::new(move |change| {
+            *slot.lock() = change.new_item().cloned();
+            notify.notify_one();
+        })
+    }
+}
+
+impl TypeMapBound for RcLocal {
+    type Dyn = dyn Any;
+    type Pointer<ItemType: ?Sized + 'static> = Rc<ItemType>;
+    type Shared<T: 'static> = Rc<T>;
+    type Callback = dyn FnMut(&TypeMapChange<Self, dyn Any>);
+
+    fn new_shared<T: 'static>(value: T) -> Self::Shared<T> {
+        Rc::new(value)
+    }
+
+    fn make_mut<T: Clone + 'static>(shared: &mut Self::Shared<T>) -> &mut T {
+        Rc::make_mut(shared)
+    }
+
+    fn downcast<CastType: 'static>(pointer: &Rc<dyn Any>) -> Option<Rc<CastType>> {
+        pointer.downcast_rc()
+    }
+
+    fn wait_for_callback(slot: TypeMapSlot<Self>, notify: Rc<Notify>) -> Box<Self::Callback> {
+        Box::new(move |change| {
+            *slot.lock() = change.new_item().cloned();
+            notify.notify_one();
+        })
+    }
+}
+
+impl<ItemType: Any> TypeMapValue<Local> for ItemType {
+    fn into_pointer(self) -> Arc<dyn Any> {
+        Arc::new(self)
+    }
+}
+
+impl<ItemType: Any + Send + Sync> TypeMapValue<Sendable> for ItemType {
+    fn into_pointer(self) -> Arc<dyn Any + Send + Sync> {
+        Arc::new(self)
+    }
+}
+
+impl<ItemType: Any> TypeMapValue<RcLocal> for ItemType {
+    fn into_pointer(self) -> Rc<dyn Any> {
+        Rc::new(self)
+    }
+}
+
+impl<ItemType, Callback> TypeMapCallback<Local, ItemType> for Callback
+where
+    ItemType: Any,
+    Callback: FnMut(&TypeMapChange<Local, ItemType>) + 'static,
+{
+    fn into_boxed(mut self) -> Box<<Local as TypeMapBound>::Callback> {
+        Box::new(move |change: &TypeMapChange<Local, dyn Any>| {
+            if let Some(change) = change.downcast::<ItemType>() {
+                self(&change);
+            }
+        })
+    }
+}
+
+impl<Callback> TypeMapCallback<Local, dyn Any> for Callback
+where
+    Callback: FnMut(&TypeMapChange<Local, dyn Any>) + 'static,
+{
+    fn into_boxed(self) -> Box<<Local as TypeMapBound>::Callback> {
+        Box::new(self)
+    }
+}
+
+impl<ItemType, Callback> TypeMapCallback<Sendable, ItemType> for Callback
+where
+    ItemType: Any,
+    Callback: FnMut(&TypeMapChange<Sendable, ItemType>) + Send + 'static,
+{
+    fn into_boxed(mut self) -> Box<<Sendable as TypeMapBound>::Callback> {
+        Box::new(
+            move |change: &TypeMapChange<Sendable, dyn Any + Send + Sync>| {
+                if let Some(change) = change.downcast::<ItemType>() {
+                    self(&change);
+                }
+            },
+        )
+    }
+}
+
+impl<Callback> TypeMapCallback<Sendable, dyn Any + Send + Sync> for Callback
+where
+    Callback: FnMut(&TypeMapChange<Sendable, dyn Any + Send + Sync>) + Send + 'static,
+{
+    fn into_boxed(self) -> Box<<Sendable as TypeMapBound>::Callback> {
+        Box::new(self)
+    }
+}
+
+impl<ItemType, Callback> TypeMapCallback<RcLocal, ItemType> for Callback
+where
+    ItemType: Any,
+    Callback: FnMut(&TypeMapChange<RcLocal, ItemType>) + 'static,
+{
+    fn into_boxed(mut self) -> Box<<RcLocal as TypeMapBound>::Callback> {
+        Box::new(move |change: &TypeMapChange<RcLocal, dyn Any>| {
+            if let Some(change) = change.downcast::<ItemType>() {
+                self(&change);
+            }
+        })
+    }
+}
+
+impl<Callback> TypeMapCallback<RcLocal, dyn Any> for Callback
+where
+    Callback: FnMut(&TypeMapChange<RcLocal, dyn Any>) + 'static,
+{
+    fn into_boxed(self) -> Box<<RcLocal as TypeMapBound>::Callback> {
+        Box::new(self)
+    }
+}
+
+fn debug_any<ItemType: Debug + 'static>(
+    value: &dyn Any,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    if let Some(value) = value.downcast_ref::<ItemType>() {
+        value.fmt(f)
+    } else {
+        unreachable!()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{
+        rc::Rc,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use super::{Local, RcLocal, Sendable, TypeMap, TypeMapChange};
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct A {
+        value: String,
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct B {
+        value: String,
+    }
+
+    // the same suite runs on every flavour, the flavours only differ in what they can store
+    macro_rules! type_map_tests {
+        ($($flavour:ident: $Bound:ident,)*) => {$(
+            mod $flavour {
+                use std::{any::TypeId, sync::Arc};
+
+                use parking_lot::Mutex;
+
+                use super::{A, B};
+                use crate::containers::type_map::{
+                    $Bound, MergePolicy, TypeMap, TypeMapBound, TypeMapChange, TypeMapItem,
+                };
+
+                type Map = TypeMap<$Bound>;
+
+                #[test]
+                fn store_and_remove() {
+                    let mut map = Map::new();
+
+                    assert!(
+                        map.insert(A {
+                            value: "A0".to_string(),
+                        })
+                        .old_item
+                        .is_none()
+                    );
+
+                    assert_eq!(
+                        *map.insert(A {
+                            value: "A1".to_string(),
+                        })
+                        .old_item
+                        .unwrap(),
+                        A {
+                            value: "A0".to_string(),
+                        }
+                    );
+
+                    assert!(
+                        map.insert(B {
+                            value: "B".to_string(),
+                        })
+                        .old_item
+                        .is_none()
+                    );
+
+                    assert_eq!(map.get_item_ref::<A>().unwrap().value, "A1");
+                    assert_eq!(map.get_item_ref::<B>().unwrap().value, "B");
+
+                    let items: Vec<TypeMapItem<$Bound, <$Bound as TypeMapBound>::Dyn>> =
+                        map.iter().collect();
+                    assert_eq!(items.len(), 2);
+                    if items[0].downcast::<A>().is_some() {
+                        assert!(items[1].downcast::<B>().is_some());
+                    } else {
+                        assert!(items[0].downcast::<B>().is_some());
+                        assert!(items[1].downcast::<A>().is_some());
+                    }
+
+                    assert_eq!(
+                        *map.remove::<A>().unwrap(),
+                        A {
+                            value: "A1".to_string(),
+                        }
+                    );
+                    assert!(map.get_item_ref::<A>().is_none());
+
+                    assert_eq!(
+                        *map.remove::<B>().unwrap(),
+                        B {
+                            value: "B".to_string(),
+                        }
+                    );
+                    assert!(map.get_item_ref::<B>().is_none());
+                }
+
+                #[test]
+                fn subscribe() {
+                    let mut map = Map::new();
+
+                    let changes = Arc::new(Mutex::new(Vec::new()));
+
+                    let subscription = map.subscribe::<A>({
+                        let changes = changes.clone();
+                        move |change: &TypeMapChange<$Bound, A>| {
+                            changes.lock().push(match change {
+                                TypeMapChange::Inserted { new_item } => {
+                                    (None, Some(new_item.value.clone()))
+                                }
+                                TypeMapChange::Replaced { old_item, new_item } => {
+                                    (Some(old_item.value.clone()), Some(new_item.value.clone()))
+                                }
+                                TypeMapChange::Removed { old_item } => {
+                                    (Some(old_item.value.clone()), None)
+                                }
+                            });
+                        }
+                    });
+
+                    map.insert(A {
+                        value: "A0".to_string(),
+                    });
+                    map.insert(B {
+                        value: "B".to_string(),
+                    });
+                    map.insert(A {
+                        value: "A1".to_string(),
+                    });
+                    map.remove::<B>();
+                    map.remove::<A>();
+                    map.get_or_insert_item_ref(|| A {
+                        value: "A2".to_string(),
+                    });
+                    map.get_or_insert_item_ref(|| A {
+                        value: "A3".to_string(),
+                    });
+
+                    drop(subscription);
+
+                    map.insert(A {
+                        value: "A4".to_string(),
+                    });
+
+                    assert_eq!(
+                        *changes.lock(),
+                        vec![
+                            (None, Some("A0".to_string())),
+                            (Some("A0".to_string()), Some("A1".to_string())),
+                            (Some("A1".to_string()), None),
+                            (None, Some("A2".to_string())),
+                        ]
+                    );
+                }
+
+                #[tokio::test]
+                async fn wait_for() {
+                    let mut map = Map::new();
+
+                    let present = map.insert(B {
+                        value: "B".to_string(),
+                    });
+                    assert!(std::ptr::eq(
+                        &*map.wait_for::<B>().await,
+                        &*present.new_item
+                    ));
+
+                    let waiting = map.wait_for::<A>();
+
+                    map.insert(A {
+                        value: "A".to_string(),
+                    });
+
+                    assert_eq!(
+                        *waiting.await,
+                        A {
+                            value: "A".to_string(),
+                        }
+                    );
+                }
+
+                #[test]
+                fn type_names_and_debug() {
+                    let mut map = Map::new();
+
+                    let a = map.insert_with_debug(A {
+                        value: "A".to_string(),
+                    });
+                    let b = map.insert(B {
+                        value: "B".to_string(),
+                    });
+
+                    let type_name_a = std::any::type_name::<A>();
+                    let type_name_b = std::any::type_name::<B>();
+
+                    assert_eq!(a.new_item.type_name(), type_name_a);
+                    assert_eq!(b.new_item.type_name(), type_name_b);
+
+                    assert_eq!(format!("{:?}", a.new_item), "A { value: \"A\" }");
+                    assert_eq!(
+                        format!("{:?}", b.new_item),
+                        format!("{type_name_b} {{ .. }}")
+                    );
+                    assert_eq!(
+                        format!("{:?}", map.get_item_ref_any(TypeId::of::<A>()).unwrap()),
+                        "A { value: \"A\" }"
+                    );
+
+                    let map_debug = format!("{map:?}");
+                    assert!(map_debug.contains(&format!("{type_name_a:?}: A {{ value: \"A\" }}")));
+                    assert!(
+                        map_debug.contains(&format!("{type_name_b:?}: {type_name_b} {{ .. }}"))
+                    );
+                }
+
+                #[test]
+                fn clone_and_merge() {
+                    let mut defaults = Map::new();
+                    defaults.insert(A {
+                        value: "default A".to_string(),
+                    });
+                    defaults.insert(B {
+                        value: "default B".to_string(),
+                    });
+
+                    let mut overrides = Map::new();
+                    overrides.insert(A {
+                        value: "override A".to_string(),
+                    });
+
+                    let mut map = defaults.clone();
+                    assert!(std::ptr::eq(
+                        &*map.get_item_ref::<A>().unwrap(),
+                        &*defaults.get_item_ref::<A>().unwrap()
+                    ));
+
+                    map.merge_from(&overrides, MergePolicy::KeepExisting);
+                    assert_eq!(map.len(), 2);
+                    assert_eq!(map.get_item_ref::<A>().unwrap().value, "default A");
+
+                    map.merge_from(&overrides, MergePolicy::Override);
+                    assert_eq!(map.len(), 2);
+                    assert_eq!(map.get_item_ref::<A>().unwrap().value, "override A");
+                    assert_eq!(map.get_item_ref::<B>().unwrap().value, "default B");
+
+                    // the original map is not affected by the modification of the clone
+                    assert_eq!(defaults.get_item_ref::<A>().unwrap().value, "default A");
+                }
+
+                #[test]
+                fn snapshot() {
+                    let mut map = Map::new();
+                    map.insert(A {
+                        value: "A0".to_string(),
+                    });
+
+                    let snapshot = map.snapshot();
+
+                    map.insert(A {
+                        value: "A1".to_string(),
+                    });
+                    map.insert(B {
+                        value: "B".to_string(),
+                    });
+
+                    assert_eq!(snapshot.len(), 1);
+                    assert_eq!(snapshot.get_item_ref::<A>().unwrap().value, "A0");
+                    assert!(snapshot.get_item_ref::<B>().is_none());
+
+                    let extended_snapshot = snapshot.with(B {
+                        value: "B".to_string(),
+                    });
+                    assert_eq!(snapshot.len(), 1);
+                    assert_eq!(extended_snapshot.len(), 2);
+                    assert!(std::ptr::eq(
+                        &*snapshot.get_item_ref::<A>().unwrap(),
+                        &*extended_snapshot.get_item_ref::<A>().unwrap()
+                    ));
+
+                    let reduced_snapshot = extended_snapshot.without::<A>();
+                    assert_eq!(extended_snapshot.len(), 2);
+                    assert_eq!(reduced_snapshot.len(), 1);
+
+                    let restored_map = Map::from(extended_snapshot);
+                    assert_eq!(restored_map.get_item_ref::<A>().unwrap().value, "A0");
+                    assert_eq!(restored_map.get_item_ref::<B>().unwrap().value, "B");
+                }
+            }
+        )*};
+    }
+
+    type_map_tests! {
+        local: Local,
+        sendable: Sendable,
+        rc_local: RcLocal,
+    }
+
+    #[test]
+    fn rc_local_callbacks_do_not_have_to_be_send() {
+        let mut map = TypeMap::<RcLocal>::new();
+
+        let changes = Rc::new(parking_lot::Mutex::new(0));
+        let _subscription = map.subscribe::<A>({
+            // the callbacks of a local map do not have to be Send
+            let changes = changes.clone();
+            move |_change: &TypeMapChange<RcLocal, A>| *changes.lock() += 1
+        });
+
+        let item = map
+            .insert(A {
+                value: "A".to_string(),
+            })
+            .new_item;
+        assert!(Rc::ptr_eq(
+            map.get_item_ref::<A>().unwrap().as_rc_ref(),
+            item.as_rc_ref()
+        ));
+        assert_eq!(map.len(), 1);
+
+        let removed_item = map.remove::<A>().unwrap();
+        assert!(Rc::ptr_eq(&removed_item, item.as_rc_ref()));
+        assert!(map.is_empty());
+        assert_eq!(*changes.lock(), 2);
+    }
+
+    #[test]
+    fn drop_subscriptions_from_a_callback() {
+        let mut map = TypeMap::<Sendable>::new();
+
+        let subscriptions = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let subscription = map.subscribe::<A>({
+                let subscriptions = subscriptions.clone();
+                let calls = calls.clone();
+                move |_change: &TypeMapChange<Sendable, A>| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    // drops its own subscription and the other one, which is then skipped
+                    subscriptions.lock().clear();
+                }
+            });
+            subscriptions.lock().push(subscription);
+        }
+
+        map.insert(A {
+            value: "A".to_string(),
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        map.remove::<A>();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn sendable_into_local() {
+        let mut sendable_map = TypeMap::<Sendable>::new();
+        let inserted_item = sendable_map
+            .insert_with_debug(A {
+                value: "A".to_string(),
+            })
+            .new_item;
+
+        let local_map = TypeMap::<Local>::from(sendable_map);
+
+        let item = local_map.get_item_ref::<A>().unwrap();
+        assert!(Arc::ptr_eq(item.as_arc_ref(), inserted_item.as_arc_ref()));
+        assert_eq!(format!("{item:?}"), "A { value: \"A\" }");
+    }
+
+    #[test]
+    fn sendable_is_send_and_sync() {
+        fn assert_send_and_sync<T: Send + Sync>() {}
+
+        assert_send_and_sync::<TypeMap<Sendable>>();
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+    };
+
+    use super::{Sendable, TypeMap};
+
+    #[test]
+    fn concurrent_item_type_locks() {
+        loom::model(|| {
+            // the threads share the map, a clone would have its own type locks
+            let map = Arc::new(TypeMap::<Sendable>::new());
+            let holders = Arc::new(AtomicUsize::new(0));
+
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let map = map.clone();
+                    let holders = holders.clone();
+                    thread::spawn(move || {
+                        let _guard = map.lock_item_type::<usize>();
+                        assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0);
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            // the last guard removes the lock of the type
+            assert!(map.item_type_locks.lock().is_empty());
+        });
+    }
+}
@@ -0,0 +1,316 @@
+//! # Type Registry
+//!
+//! Maps stable string tags to the types stored in a [`TypeMap`], so that the content of a map can
+//! be converted into a [`serde_json::Value`] and back. The value is an object keyed by the tags,
+//! it can be written with any self-describing serde format (e.g. JSON, CBOR or MessagePack).
+//!
+//! The items are always serialized through [`serde_json::Value`], there is no path that streams
+//! them into an arbitrary serde `Serializer`. So the data model of JSON applies to every format:
+//! e.g. map keys become strings, byte buffers become arrays of numbers, non-finite floats become
+//! null, and integers that do not fit into 64 bits cannot be saved.
+//!
+//! Items of unregistered types are skipped when saving. Entries with unknown tags are kept in an
+//! [`UnregisteredEntries`] item on load and they are written back on the next save.
+
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    cast::AsAny,
+    containers::{
+        multi_type_dict::MultiTypeDict,
+        rc_multi_type_dict::RcMultiTypeDict,
+        sendable_multi_type_dict::SendableMultiTypeDict,
+        type_map::{Local, RcLocal, Sendable, TypeMap, TypeMapBound, TypeMapValue},
+    },
+};
+
+type SerializeFn = fn(&dyn Any) -> serde_json::Result<Value>;
+type InsertFn<Bound> = fn(Value, &mut TypeMap<Bound>) -> serde_json::Result<()>;
+
+struct RegisteredType {
+    tag: String,
+    serialize: SerializeFn,
+    insert: InsertFn<Local>,
+    insert_rc: InsertFn<RcLocal>,
+    insert_sendable: Option<InsertFn<Sendable>>,
+}
+
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: BTreeMap<TypeId, RegisteredType>,
+    type_ids_by_tag: BTreeMap<String, TypeId>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnregisteredEntries(pub BTreeMap<String, Value>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    TagAlreadyRegistered,
+    TypeAlreadyRegistered,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<ItemType>(&mut self, tag: impl Into<String>) -> Result<(), RegisterError>
+    where
+        ItemType: Any + Send + Sync + Serialize + DeserializeOwned,
+    {
+        self.register_type::<ItemType>(
+            tag.into(),
+            Some(|value, map| {
+                map.insert(serde_json::from_value::<ItemType>(value)?);
+                Ok(())
+            }),
+        )
+    }
+
+    /// Registers a type that can only be stored in a [`MultiTypeDict`]. Entries of such types are
+    /// treated as unregistered when loading a [`SendableMultiTypeDict`].
+    pub fn register_local<ItemType>(&mut self, tag: impl Into<String>) -> Result<(), RegisterError>
+    where
+        ItemType: Any + Serialize + DeserializeOwned,
+    {
+        self.register_type::<ItemType>(tag.into(), None)
+    }
+
+    pub fn tag_of(&self, type_id: TypeId) -> Option<&str> {
+        self.types
+            .get(&type_id)
+            .map(|registered_type| registered_type.tag.as_str())
+    }
+
+    pub fn to_value<Bound: TypeMapBound>(&self, map: &TypeMap<Bound>) -> serde_json::Result<Value> {
+        let mut object = map
+            .get_item_ref::<UnregisteredEntries>()
+            .as_deref()
+            .cloned()
+            .unwrap_or_default()
+            .0;
+
+        for item in map.iter() {
+            let value = AsAny::as_any(&*item);
+
+            // the type id of the stored value is used, not the key of the item in the map
+            if let Some(registered_type) = self.types.get(&value.type_id()) {
+                object.insert(
+                    registered_type.tag.clone(),
+                    (registered_type.serialize)(value)?,
+                );
+            }
+        }
+
+        Ok(Value::Object(object.into_iter().collect()))
+    }
+
+    pub fn dict_from_value(&self, value: Value) -> serde_json::Result<MultiTypeDict> {
+        self.load_map(value, |registered_type| Some(registered_type.insert))
+    }
+
+    pub fn rc_dict_from_value(&self, value: Value) -> serde_json::Result<RcMultiTypeDict> {
+        self.load_map(value, |registered_type| Some(registered_type.insert_rc))
+    }
+
+    pub fn sendable_dict_from_value(
+        &self,
+        value: Value,
+    ) -> serde_json::Result<SendableMultiTypeDict> {
+        self.load_map(value, |registered_type| registered_type.insert_sendable)
+    }
+
+    fn register_type<ItemType>(
+        &mut self,
+        tag: String,
+        insert_sendable: Option<InsertFn<Sendable>>,
+    ) -> Result<(), RegisterError>
+    where
+        ItemType: Any + Serialize + DeserializeOwned,
+    {
+        let type_id = TypeId::of::<ItemType>();
+
+        if self.type_ids_by_tag.contains_key(&tag) {
+            return Err(RegisterError::TagAlreadyRegistered);
+        }
+
+        if self.types.contains_key(&type_id) {
+            return Err(RegisterError::TypeAlreadyRegistered);
+        }
+
+        self.type_ids_by_tag.insert(tag.clone(), type_id);
+        self.types.insert(
+            type_id,
+            RegisteredType {
+                tag,
+                serialize: |value| {
+                    if let Some(value) = value.downcast_ref::<ItemType>() {
+                        serde_json::to_value(value)
+                    } else {
+                        unreachable!()
+                    }
+                },
+                insert: |value, map| {
+                    map.insert(serde_json::from_value::<ItemType>(value)?);
+                    Ok(())
+                },
+                insert_rc: |value, map| {
+                    map.insert(serde_json::from_value::<ItemType>(value)?);
+                    Ok(())
+                },
+                insert_sendable,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn registered_type_by_tag(&self, tag: &str) -> Option<&RegisteredType> {
+        self.type_ids_by_tag
+            .get(tag)
+            .and_then(|type_id| self.types.get(type_id))
+    }
+
+    fn load_map<Bound: TypeMapBound>(
+        &self,
+        value: Value,
+        insert_fn: impl Fn(&RegisteredType) -> Option<InsertFn<Bound>>,
+    ) -> serde_json::Result<TypeMap<Bound>>
+    where
+        UnregisteredEntries: TypeMapValue<Bound>,
+    {
+        let mut map = TypeMap::new();
+
+        let mut unregistered_entries = UnregisteredEntries::default();
+        for (tag, value) in Self::into_object(value)? {
+            match self.registered_type_by_tag(&tag).and_then(&insert_fn) {
+                Some(insert) => insert(value, &mut map)?,
+                None => {
+                    unregistered_entries.0.insert(tag, value);
+                }
+            }
+        }
+
+        if !unregistered_entries.0.is_empty() {
+            map.insert(unregistered_entries);
+        }
+
+        Ok(map)
+    }
+
+    fn into_object(value: Value) -> serde_json::Result<serde_json::Map<String, Value>> {
+        match value {
+            Value::Object(object) => Ok(object),
+            _ => Err(serde::de::Error::custom(
+                "a map can only be loaded from an object",
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::containers::multi_type_dict::MultiTypeDict;
+
+    use super::{RegisterError, TypeRegistry, UnregisteredEntries};
+
+    #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+    struct A {
+        value: String,
+    }
+
+    #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+    struct B(usize);
+
+    struct NotSerializable;
+
+    fn create_registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<A>("a").unwrap();
+        registry.register::<B>("b").unwrap();
+
+        registry
+    }
+
+    #[test]
+    fn register() {
+        let mut registry = create_registry();
+
+        assert_eq!(
+            registry.register::<usize>("a"),
+            Err(RegisterError::TagAlreadyRegistered)
+        );
+        assert_eq!(
+            registry.register::<A>("c"),
+            Err(RegisterError::TypeAlreadyRegistered)
+        );
+        assert_eq!(registry.tag_of(std::any::TypeId::of::<B>()), Some("b"));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let registry = create_registry();
+
+        let mut dict = MultiTypeDict::new();
+        dict.insert(A {
+            value: "A".to_string(),
+        });
+        dict.insert(B(7));
+        dict.insert(NotSerializable);
+
+        let value = registry.to_value(&dict).unwrap();
+        assert_eq!(value, json!({ "a": { "value": "A" }, "b": 7 }));
+
+        let json = serde_json::to_string(&value).unwrap();
+        let value = serde_json::from_str(&json).unwrap();
+
+        let dict = registry.dict_from_value(value).unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get_item_ref::<A>().unwrap().value, "A");
+        assert_eq!(*dict.get_item_ref::<B>().unwrap(), B(7));
+    }
+
+    #[test]
+    fn keep_unknown_tags() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<A>("a").unwrap();
+
+        let mut dict = registry
+            .sendable_dict_from_value(json!({ "a": { "value": "A" }, "b": 7 }))
+            .unwrap();
+
+        assert_eq!(
+            dict.get_item_ref::<UnregisteredEntries>()
+                .unwrap()
+                .0
+                .get("b"),
+            Some(&json!(7))
+        );
+
+        dict.insert(A {
+            value: "modified A".to_string(),
+        });
+
+        assert_eq!(
+            registry.to_value(&dict).unwrap(),
+            json!({ "a": { "value": "modified A" }, "b": 7 })
+        );
+    }
+
+    #[test]
+    fn load_from_non_object() {
+        let registry = create_registry();
+
+        assert!(registry.dict_from_value(json!([1, 2, 3])).is_err());
+    }
+}
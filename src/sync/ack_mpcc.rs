@@ -0,0 +1,306 @@
+//! # Acknowledged Multi Producer, Collective Consumer
+//!
+//! An [`mpcc`] channel with at-least-once delivery. A received message is wrapped in a [`Lease`]
+//! that has to be acknowledged. A message that is not acknowledged, because the lease is nacked
+//! or dropped, e.g. while a worker panics, goes back to the end of the queue.
+//!
+//! A message is delivered at most `max_deliveries` times, after that it is sent to the dead
+//! letter receiver instead of the queue. The receivers are not disconnected while a lease is
+//! alive, since it may put its message back.
+
+use std::{any::Any, collections::VecDeque, ops::Deref, time::Duration};
+
+use or_die::OrDie;
+
+use super::mpcc::{
+    self, QueueReceiver, QueueSender, RecvError, RecvTimeoutError, SendError, TryRecvError,
+};
+
+struct Delivery<T> {
+    msg: T,
+    delivery_count: usize,
+}
+
+type DeliveryQueue<T> = VecDeque<Delivery<T>>;
+
+pub struct Sender<T: Send> {
+    inner: QueueSender<DeliveryQueue<T>>,
+}
+
+pub struct Receiver<T: Send> {
+    inner: QueueReceiver<DeliveryQueue<T>>,
+    dead_letters: mpcc::Sender<T>,
+    max_deliveries: usize,
+}
+
+/// A received message that goes back to the queue unless it is acknowledged.
+pub struct Lease<T: Send> {
+    // only None after the lease is acknowledged or nacked
+    delivery: Option<Delivery<T>>,
+    queue: QueueSender<DeliveryQueue<T>>,
+    dead_letters: mpcc::Sender<T>,
+    max_deliveries: usize,
+}
+
+pub fn channel<T: Send>(max_deliveries: usize) -> (Sender<T>, Receiver<T>, mpcc::Receiver<T>) {
+    let (sender, receiver) = mpcc::channel_with_queue(VecDeque::new());
+    let (dead_letter_sender, dead_letter_receiver) = mpcc::channel();
+
+    (
+        Sender { inner: sender },
+        Receiver {
+            inner: receiver,
+            dead_letters: dead_letter_sender,
+            max_deliveries: max_deliveries.max(1),
+        },
+        dead_letter_receiver,
+    )
+}
+
+impl<T: Send> Sender<T> {
+    pub fn send(&self, msg: T) -> Result<(), SendError> {
+        self.inner.send(Delivery {
+            msg,
+            delivery_count: 0,
+        })
+    }
+
+    pub fn send_batch(&self, msgs: impl IntoIterator<Item = T>) -> Result<(), SendError> {
+        self.inner.send_batch(msgs.into_iter().map(|msg| Delivery {
+            msg,
+            delivery_count: 0,
+        }))
+    }
+
+    /// See [`mpcc::QueueSender::close`].
+    pub fn close<Reason: Any + Send + Sync>(&self, reason: Reason) {
+        self.inner.close(reason);
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    pub async fn recv_async(&mut self) -> Result<Lease<T>, RecvError> {
+        let (delivery, queue) = self.inner.recv_async_with(Self::take).await?;
+        Ok(self.lease(delivery, queue))
+    }
+
+    pub fn try_recv(&self) -> Result<Lease<T>, TryRecvError> {
+        let (delivery, queue) = self.inner.try_recv_with(Self::take)?;
+        Ok(self.lease(delivery, queue))
+    }
+
+    pub fn recv_blocking(&self) -> Result<Lease<T>, RecvError> {
+        let (delivery, queue) = self.inner.recv_blocking_with(Self::take)?;
+        Ok(self.lease(delivery, queue))
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Lease<T>, RecvTimeoutError> {
+        let (delivery, queue) = self.inner.recv_timeout_with(timeout, Self::take)?;
+        Ok(self.lease(delivery, queue))
+    }
+
+    /// Called while the queue is locked, so the sender of the lease is counted before another
+    /// receiver can find the queue empty and the channel disconnected.
+    fn take(
+        inner: &QueueReceiver<DeliveryQueue<T>>,
+        delivery: Delivery<T>,
+    ) -> (Delivery<T>, QueueSender<DeliveryQueue<T>>) {
+        (delivery, inner.create_sender())
+    }
+
+    fn lease(&self, mut delivery: Delivery<T>, queue: QueueSender<DeliveryQueue<T>>) -> Lease<T> {
+        delivery.delivery_count += 1;
+
+        Lease {
+            delivery: Some(delivery),
+            queue,
+            dead_letters: self.dead_letters.clone(),
+            max_deliveries: self.max_deliveries,
+        }
+    }
+}
+
+impl<T: Send> Lease<T> {
+    /// How many times the message was received, including this one.
+    pub fn delivery_count(&self) -> usize {
+        self.delivery.as_ref().or_die().delivery_count
+    }
+
+    /// Marks the message as processed and gives it back to the caller.
+    pub fn ack(mut self) -> T {
+        self.delivery.take().or_die().msg
+    }
+
+    /// Puts the message back to the queue, or sends it to the dead letter receiver if it was
+    /// delivered `max_deliveries` times.
+    pub fn nack(mut self) {
+        self.give_back();
+    }
+
+    fn give_back(&mut self) {
+        if let Some(delivery) = self.delivery.take() {
+            if delivery.delivery_count >= self.max_deliveries {
+                // if nobody listens to the dead letters, then the message is dropped
+                let _ = self.dead_letters.send(delivery.msg);
+            } else {
+                self.queue.send_back(delivery);
+            }
+        }
+    }
+}
+
+impl<T: Send> Deref for Lease<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.delivery.as_ref().or_die().msg
+    }
+}
+
+impl<T: Send> Drop for Lease<T> {
+    fn drop(&mut self) {
+        self.give_back();
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            dead_letters: self.dead_letters.clone(),
+            max_deliveries: self.max_deliveries,
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use super::channel;
+    use crate::sync::mpcc::{RecvError, TryRecvError};
+
+    #[test]
+    fn ack_and_nack() {
+        let (sender, receiver, _dead_letters) = channel::<usize>(3);
+
+        sender.send_batch([0, 1]).unwrap();
+
+        let lease = receiver.try_recv().unwrap();
+        assert_eq!(*lease, 0);
+        assert_eq!(lease.delivery_count(), 1);
+        lease.nack();
+
+        assert_eq!(receiver.try_recv().unwrap().ack(), 1);
+
+        // dropped without an ack
+        drop(receiver.try_recv().unwrap());
+
+        let lease = receiver.try_recv().unwrap();
+        assert_eq!(*lease, 0);
+        assert_eq!(lease.delivery_count(), 3);
+        assert_eq!(lease.ack(), 0);
+
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn dead_letters() {
+        let (sender, receiver, dead_letters) = channel::<usize>(2);
+
+        sender.send(7).unwrap();
+
+        receiver.try_recv().unwrap().nack();
+        receiver.try_recv().unwrap().nack();
+
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        assert_eq!(dead_letters.try_recv().unwrap(), 7);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn redeliver_after_panic() {
+        let (sender, receiver, _dead_letters) = channel::<usize>(usize::MAX);
+
+        sender.send(7).unwrap();
+        drop(sender);
+
+        let failing_worker = tokio::spawn({
+            let mut receiver = receiver.clone();
+            async move {
+                let _lease = receiver.recv_async().await.unwrap();
+                panic!("the worker failed");
+            }
+        });
+        assert!(failing_worker.await.is_err());
+
+        let mut receiver = receiver;
+        let lease = tokio::time::timeout(Duration::from_secs(2), receiver.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lease.delivery_count(), 2);
+        assert_eq!(lease.ack(), 7);
+
+        assert!(matches!(
+            receiver.recv_async().await,
+            Err(RecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn lease_keeps_receivers_connected() {
+        let (sender, receiver, _dead_letters) = channel::<usize>(usize::MAX);
+
+        sender.send(7).unwrap();
+        drop(sender);
+
+        let lease = receiver.try_recv().unwrap();
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        drop(lease);
+        assert_eq!(receiver.try_recv().unwrap().ack(), 7);
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::thread;
+
+    use super::channel;
+    use crate::sync::mpcc::TryRecvError;
+
+    #[test]
+    fn lease_keeps_receivers_connected() {
+        loom::model(|| {
+            let (sender, receiver, _dead_letters) = channel::<usize>(usize::MAX);
+            sender.send(7).unwrap();
+            drop(sender);
+
+            let leasing_receiver = receiver.clone();
+            let leasing_thread = thread::spawn(move || {
+                // dropped without an ack, e.g. by a panicking worker
+                drop(leasing_receiver.try_recv());
+            });
+
+            let disconnected = matches!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+            leasing_thread.join().unwrap();
+
+            // a receiver must not give up while the message can still be put back
+            if disconnected {
+                assert!(receiver.inner.is_empty());
+            }
+        });
+    }
+}
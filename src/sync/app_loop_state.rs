@@ -25,8 +25,9 @@ impl AppLoopState {
         AppLoopStateWatcher(self.0.subscribe())
     }
 
+    /// The state changes even if there is no watcher yet, the later watchers see it stopped.
     pub fn stop_loop(&self) {
-        let _ = self.0.send(false);
+        self.0.send_replace(false);
     }
 
     pub fn should_run(&self) -> bool {
@@ -39,13 +40,10 @@ impl AppLoopStateWatcher {
         *self.0.borrow()
     }
 
+    /// Resolves right away if the loop is already stopped.
     pub async fn wait_for_quit(&self) {
         let mut run_loop = self.0.clone();
-        while let Ok(()) = run_loop.changed().await {
-            if !*run_loop.borrow() {
-                break;
-            }
-        }
+        let _ = run_loop.wait_for(|run| !*run).await;
     }
 }
 
@@ -73,4 +71,14 @@ mod tests {
             _ = state_watcher.wait_for_quit() => {}
         }
     }
+
+    #[tokio::test]
+    async fn stop_without_watchers() {
+        let state = AppLoopState::new();
+        state.stop_loop();
+
+        assert!(!state.should_run());
+        assert!(!state.watcher().should_run());
+        state.watcher().wait_for_quit().await;
+    }
 }
@@ -10,11 +10,15 @@
 //! # }
 //! ```
 
-use std::{ops::Deref, sync::Arc};
+use std::{ops::Deref, sync::Arc, time::Duration};
 
-use tokio::sync::Notify;
+use tokio::time::Instant;
 
-use super::types::{ArcRwLock, RwLockReadGuard, arc_rw_lock_new};
+use super::{
+    primitives::Notify,
+    timeout::{Timeout, with_deadline, with_timeout},
+    types::{ArcRwLock, RwLockReadGuard, arc_rw_lock_new},
+};
 
 pub struct AsyncItem<T: Send> {
     value: ArcRwLock<Option<T>>,
@@ -77,14 +81,32 @@ impl<T: Send> AsyncItem<T> {
 
     pub async fn read(&self) -> AsyncItemReadGuard<'_, T> {
         loop {
+            // the notified future has to exist before checking the value, otherwise a value set
+            // between the check and the await would not wake this reader up
+            let notified = self.notify.notified();
+
             if let Some(guard) = self.try_read() {
                 break guard;
             }
 
-            self.notify.notified().await;
+            notified.await;
         }
     }
 
+    pub async fn read_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<AsyncItemReadGuard<'_, T>, Timeout> {
+        with_timeout(timeout, self.read()).await
+    }
+
+    pub async fn read_deadline(
+        &self,
+        deadline: Instant,
+    ) -> Result<AsyncItemReadGuard<'_, T>, Timeout> {
+        with_deadline(deadline, self.read()).await
+    }
+
     pub fn try_read(&self) -> Option<AsyncItemReadGuard<'_, T>> {
         let value_guard = self.value.read();
         if value_guard.is_some() {
@@ -95,13 +117,13 @@ impl<T: Send> AsyncItem<T> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod test {
     use std::{sync::Arc, time::Duration};
 
     use tokio::time::sleep;
 
-    use super::AsyncItem;
+    use super::{AsyncItem, Timeout};
 
     #[tokio::test(flavor = "multi_thread")]
     async fn set_then_multiple_get() {
@@ -145,4 +167,52 @@ mod test {
             }
         }
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_timeout() {
+        let item = Arc::new(AsyncItem::new());
+
+        assert!(matches!(
+            item.read_timeout(Duration::from_secs(1)).await,
+            Err(Timeout)
+        ));
+
+        let setter = tokio::spawn({
+            let item = item.clone();
+            async move {
+                sleep(Duration::from_millis(500)).await;
+                item.set(7).await;
+            }
+        });
+
+        assert_eq!(*item.read_timeout(Duration::from_secs(1)).await.unwrap(), 7);
+        setter.await.unwrap();
+
+        // too large for a deadline, so it waits without one
+        assert_eq!(*item.read_timeout(Duration::MAX).await.unwrap(), 7);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use std::sync::Arc;
+
+    use loom::{future::block_on, thread};
+
+    use super::AsyncItem;
+
+    #[test]
+    fn reader_does_not_miss_the_value() {
+        loom::model(|| {
+            let item = Arc::new(AsyncItem::new());
+
+            let setter_thread = thread::spawn({
+                let item = item.clone();
+                move || block_on(item.set(7))
+            });
+
+            assert_eq!(*block_on(item.read()), 7);
+            setter_thread.join().unwrap();
+        });
+    }
 }
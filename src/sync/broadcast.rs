@@ -1,21 +1,57 @@
 #![allow(clippy::type_complexity)]
 
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
-use tokio::sync::Notify;
+use or_die::OrDie;
+use tokio::sync::{futures::OwnedNotified, watch};
 
 use crate::containers::object_pool::{ObjectPool, ObjectPoolIndex};
 
 use super::{
+    close_reason::CloseReason,
+    history::History,
+    primitives::{Condvar, Notify},
+    timeout::{Timeout, with_deadline, with_timeout},
+    topic::{Topic, TopicPattern},
     types::{ArcMutex, arc_mutex_new},
     usage_counter::{UsageCounter, UsageCounterWatcher},
 };
 
+type Filter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// What happens to the objects sent to a paused receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// The objects are dropped.
+    Drop,
+    /// The objects are buffered, the oldest ones are dropped above `limit`.
+    Buffer { limit: usize },
+    /// Only the latest object is kept.
+    KeepLatest,
+}
+
+struct Pause<T> {
+    mode: PauseMode,
+    buffer: VecDeque<T>,
+    missed: usize,
+}
+
 #[derive(Clone)]
 struct ReceiverQueue<T> {
     queue: ArcMutex<VecDeque<T>>,
-    is_stopped: ArcMutex<bool>,
+    // always locked after the queue
+    pause: ArcMutex<Option<Pause<T>>>,
     notify: Arc<Notify>,
+    // wakes up the threads blocked on the queue, it is always used with the queue's mutex
+    condvar: Arc<Condvar>,
+    filter: Option<Filter<T>>,
 }
 
 #[derive(Clone)]
@@ -25,15 +61,20 @@ where
 {
     receiver_queues: ArcMutex<ObjectPool<ReceiverQueue<T>>>,
     to_be_removed: ArcMutex<Vec<ObjectPoolIndex>>,
+    // always locked after receiver_queues
+    history: ArcMutex<Option<History<T>>>,
+    // kept up to date eagerly, unlike the lazily released receiver queues
+    receiver_count: Arc<watch::Sender<usize>>,
+    close_reason: ArcMutex<Option<CloseReason>>,
 }
 
-#[derive(Clone)]
 pub struct Sender<T>
 where
     T: Clone,
 {
     receiver_queues: ReceiverQueueList<T>,
-    usage_counter: UsageCounter,
+    // only None while the sender is being dropped
+    usage_counter: Option<UsageCounter>,
 }
 
 pub struct Receiver<T>
@@ -44,28 +85,103 @@ where
     queue_id: ObjectPoolIndex,
     queue: ReceiverQueue<T>,
     usage_counter_watcher: UsageCounterWatcher,
+    pending_notified: Option<Pin<Box<OwnedNotified>>>,
+}
+
+/// A sender that does not keep the receivers from getting [`RecvError::SenderDropped`], see
+/// [`Sender::downgrade`].
+pub struct WeakSender<T>
+where
+    T: Clone,
+{
+    receiver_queues: ReceiverQueueList<T>,
+    usage_counter_watcher: UsageCounterWatcher,
 }
 
 impl<T> ReceiverQueue<T>
 where
     T: Clone,
 {
-    pub fn new() -> Self {
+    pub fn new(filter: Option<Filter<T>>) -> Self {
         Self {
             queue: arc_mutex_new(VecDeque::new()),
-            is_stopped: arc_mutex_new(false),
+            pause: arc_mutex_new(None),
             notify: Arc::new(Notify::new()),
+            condvar: Arc::new(Condvar::new()),
+            filter,
         }
     }
 
-    fn add_object_if_not_stopped(&self, object: T) {
+    fn accepts(&self, object: &T) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(object))
+    }
+
+    fn add_object(&self, object: T) {
         let mut queue_guard = self.queue.lock();
-        if !*self.is_stopped.lock() {
+        if let Some(pause) = self.pause.lock().as_mut() {
+            pause.add_object(object);
+        } else {
             queue_guard.push_back(object);
             self.notify.notify_waiters();
+            self.condvar.notify_all();
+        }
+        drop(queue_guard);
+    }
+
+    fn add_objects(&self, objects: &[T]) {
+        let accepted_objects = objects
+            .iter()
+            .filter(|object| self.accepts(object))
+            .cloned();
+
+        let mut queue_guard = self.queue.lock();
+        if let Some(pause) = self.pause.lock().as_mut() {
+            accepted_objects.for_each(|object| pause.add_object(object));
+        } else {
+            let length_before = queue_guard.len();
+            queue_guard.extend(accepted_objects);
+
+            if queue_guard.len() != length_before {
+                self.notify.notify_waiters();
+                self.condvar.notify_all();
+            }
         }
         drop(queue_guard);
     }
+
+    fn wake_up_all(&self) {
+        // the lock makes sure that a blocked receiver is either already waiting on the condvar
+        // or it has not checked the sender yet
+        let queue_guard = self.queue.lock();
+        self.notify.notify_waiters();
+        self.condvar.notify_all();
+        drop(queue_guard);
+    }
+}
+
+impl<T> Pause<T> {
+    fn add_object(&mut self, object: T) {
+        match self.mode {
+            PauseMode::Drop => self.missed += 1,
+            PauseMode::Buffer { limit } => {
+                if limit == 0 {
+                    self.missed += 1;
+                    return;
+                }
+
+                if self.buffer.len() >= limit {
+                    self.buffer.pop_front();
+                    self.missed += 1;
+                }
+                self.buffer.push_back(object);
+            }
+            PauseMode::KeepLatest => {
+                self.missed += self.buffer.len();
+                self.buffer.clear();
+                self.buffer.push_back(object);
+            }
+        }
+    }
 }
 
 impl<T> ReceiverQueueList<T>
@@ -76,6 +192,9 @@ where
         Self {
             receiver_queues: arc_mutex_new(ObjectPool::new()),
             to_be_removed: arc_mutex_new(Vec::new()),
+            history: arc_mutex_new(None),
+            receiver_count: Arc::new(watch::Sender::new(0)),
+            close_reason: arc_mutex_new(None),
         }
     }
 
@@ -105,94 +224,593 @@ where
     pub fn new() -> Self {
         Self {
             receiver_queues: ReceiverQueueList::new(),
-            usage_counter: UsageCounter::new(),
+            usage_counter: Some(UsageCounter::new()),
         }
     }
 
+    /// Creates a sender that keeps the recently sent objects, so that the receivers created by
+    /// [`Sender::create_replaying_receiver`] start with them.
+    pub fn with_history(history: History<T>) -> Self {
+        let sender = Self::new();
+        *sender.receiver_queues.history.lock() = Some(history);
+        sender
+    }
+
+    /// The object is dropped if the channel is closed.
     pub fn send(&self, object: T) {
+        if self.is_closed() {
+            return;
+        }
+
         self.receiver_queues.handle_to_be_removed();
+
+        let receiver_queues_guard = self.receiver_queues.receiver_queues.lock();
+        for queue in receiver_queues_guard.iter() {
+            // the filter is evaluated before cloning, so filtered out objects are never copied
+            if queue.accepts(&object) {
+                queue.add_object(object.clone());
+            }
+        }
+
+        // recorded while the receiver list is locked, so that a replaying receiver gets every
+        // object either from the history or from the sender, but never from both
+        if let Some(history) = self.receiver_queues.history.lock().as_mut() {
+            history.record(&object);
+        }
+        drop(receiver_queues_guard);
+    }
+
+    /// Closes the channel even if other senders are alive. The receivers get the objects that
+    /// are already in their queue, then [`RecvError::Closed`] with the given reason. Only the
+    /// first close has an effect.
+    pub fn close<Reason: Any + Send + Sync>(&self, reason: Reason) {
+        {
+            let mut close_reason = self.receiver_queues.close_reason.lock();
+            if close_reason.is_some() {
+                return;
+            }
+            *close_reason = Some(CloseReason::new(reason));
+        }
+
         for queue in self.receiver_queues.receiver_queues.lock().iter() {
-            queue.add_object_if_not_stopped(object.clone());
+            queue.wake_up_all();
         }
     }
 
-    pub fn send_directly(&self, object: T, receiver: &Receiver<T>) {
-        receiver.queue.add_object_if_not_stopped(object.clone());
+    pub fn is_closed(&self) -> bool {
+        self.receiver_queues.close_reason.lock().is_some()
     }
 
-    pub fn create_receiver(&self) -> Receiver<T> {
-        let queue = ReceiverQueue::<T>::new();
-        let queue_id = self
-            .receiver_queues
+    pub fn receiver_count(&self) -> usize {
+        *self.receiver_queues.receiver_count.borrow()
+    }
+
+    /// Resolves as soon as there is at least one receiver.
+    pub async fn wait_for_receiver(&self) {
+        let mut receiver_count = self.receiver_queues.receiver_count.subscribe();
+        let _ = receiver_count.wait_for(|count| *count != 0).await;
+    }
+
+    /// Resolves when every receiver is dropped, or right away if there is none.
+    pub async fn closed(&self) {
+        let mut receiver_count = self.receiver_queues.receiver_count.subscribe();
+        let _ = receiver_count.wait_for(|count| *count == 0).await;
+    }
+
+    /// Number of objects waiting in the queue of every live receiver.
+    pub fn queue_lengths(&self) -> Vec<usize> {
+        self.receiver_queues.handle_to_be_removed();
+        self.receiver_queues
             .receiver_queues
             .lock()
-            .create_object(queue.clone());
-        Receiver {
+            .iter()
+            .map(|queue| queue.queue.lock().len())
+            .collect()
+    }
+
+    /// Sends the objects in order, every receiver queue is locked only once for the whole batch.
+    pub fn send_batch(&self, objects: impl IntoIterator<Item = T>) {
+        let objects: Vec<T> = objects.into_iter().collect();
+        if objects.is_empty() || self.is_closed() {
+            return;
+        }
+
+        self.receiver_queues.handle_to_be_removed();
+
+        let receiver_queues_guard = self.receiver_queues.receiver_queues.lock();
+        for queue in receiver_queues_guard.iter() {
+            queue.add_objects(&objects);
+        }
+
+        if let Some(history) = self.receiver_queues.history.lock().as_mut() {
+            for object in &objects {
+                history.record(object);
+            }
+        }
+        drop(receiver_queues_guard);
+    }
+
+    /// Sends the object to the given receiver even if its filter would not accept it.
+    pub fn send_directly(&self, object: T, receiver: &Receiver<T>) {
+        receiver.queue.add_object(object.clone());
+    }
+
+    pub fn create_receiver(&self) -> Receiver<T> {
+        self.create_receiver_with_filter(None)
+    }
+
+    /// Creates a receiver that only gets the objects for which `filter` returns true.
+    pub fn create_filtered_receiver(
+        &self,
+        filter: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Receiver<T> {
+        self.create_receiver_with_filter(Some(Arc::new(filter)))
+    }
+
+    /// Creates a receiver that only gets the objects whose topic matches `pattern`.
+    pub fn create_topic_receiver(&self, pattern: TopicPattern) -> Receiver<T>
+    where
+        T: Topic,
+    {
+        self.create_filtered_receiver(move |object| pattern.matches(object.topic()))
+    }
+
+    /// Creates a receiver that starts with the objects kept in the history, it works like
+    /// [`Sender::create_receiver`] if the sender has no history.
+    pub fn create_replaying_receiver(&self) -> Receiver<T> {
+        Receiver::new(
+            &self.receiver_queues,
+            self.usage_counter.as_ref().or_die().watcher(),
+            None,
+            true,
+        )
+    }
+
+    pub fn create_replaying_filtered_receiver(
+        &self,
+        filter: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Receiver<T> {
+        Receiver::new(
+            &self.receiver_queues,
+            self.usage_counter.as_ref().or_die().watcher(),
+            Some(Arc::new(filter)),
+            true,
+        )
+    }
+
+    /// Creates a sender that can only send while at least one [`Sender`] is alive.
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender {
             receiver_queues: self.receiver_queues.clone(),
-            queue_id,
-            queue,
-            usage_counter_watcher: self.usage_counter.watcher(),
+            usage_counter_watcher: self.usage_counter.as_ref().or_die().watcher(),
         }
     }
+
+    fn create_receiver_with_filter(&self, filter: Option<Filter<T>>) -> Receiver<T> {
+        Receiver::new(
+            &self.receiver_queues,
+            self.usage_counter.as_ref().or_die().watcher(),
+            filter,
+            false,
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct SenderDropped;
 
+impl<T> WeakSender<T>
+where
+    T: Clone,
+{
+    /// Returns None if every sender was already dropped.
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        self.usage_counter_watcher
+            .upgrade()
+            .map(|usage_counter| Sender {
+                receiver_queues: self.receiver_queues.clone(),
+                usage_counter: Some(usage_counter),
+            })
+    }
+}
+
+impl<T> Clone for WeakSender<T>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            receiver_queues: self.receiver_queues.clone(),
+            usage_counter_watcher: self.usage_counter_watcher.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    SenderDropped,
+    Closed(CloseReason),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    Timeout,
+    SenderDropped,
+    Closed(CloseReason),
+}
+
+impl From<SenderDropped> for RecvError {
+    fn from(_: SenderDropped) -> Self {
+        Self::SenderDropped
+    }
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(error: RecvError) -> Self {
+        match error {
+            RecvError::SenderDropped => Self::SenderDropped,
+            RecvError::Closed(reason) => Self::Closed(reason),
+        }
+    }
+}
+
+impl From<Timeout> for RecvTimeoutError {
+    fn from(_: Timeout) -> Self {
+        Self::Timeout
+    }
+}
+
 impl<T> Receiver<T>
 where
     T: Clone,
 {
+    fn new(
+        receiver_queues: &ReceiverQueueList<T>,
+        usage_counter_watcher: UsageCounterWatcher,
+        filter: Option<Filter<T>>,
+        replay_history: bool,
+    ) -> Self {
+        let queue = ReceiverQueue::<T>::new(filter);
+
+        let mut receiver_queues_guard = receiver_queues.receiver_queues.lock();
+        if replay_history && let Some(history) = receiver_queues.history.lock().as_mut() {
+            queue.queue.lock().extend(
+                history
+                    .replay()
+                    .into_iter()
+                    .filter(|object| queue.accepts(object)),
+            );
+        }
+        let queue_id = receiver_queues_guard.create_object(queue.clone());
+        drop(receiver_queues_guard);
+
+        receiver_queues
+            .receiver_count
+            .send_modify(|receiver_count| *receiver_count += 1);
+
+        Self {
+            receiver_queues: receiver_queues.clone(),
+            queue_id,
+            queue,
+            usage_counter_watcher,
+            pending_notified: None,
+        }
+    }
+
+    /// Number of objects waiting in the queue of this receiver.
+    pub fn len(&self) -> usize {
+        self.queue.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.queue.lock().is_empty()
+    }
+
+    /// Drops every object sent until [`Receiver::resume`] is called.
     pub fn stop(&mut self) {
-        *self.queue.is_stopped.lock() = true;
+        self.pause(PauseMode::Drop);
     }
 
-    pub fn resume(&mut self) {
-        *self.queue.is_stopped.lock() = false;
+    /// Holds back the objects sent until [`Receiver::resume`] is called. Pausing an already
+    /// paused receiver only changes the mode, the buffered objects are kept.
+    pub fn pause(&mut self, mode: PauseMode) {
+        let mut pause_guard = self.queue.pause.lock();
+        if let Some(pause) = pause_guard.as_mut() {
+            pause.mode = mode;
+        } else {
+            *pause_guard = Some(Pause {
+                mode,
+                buffer: VecDeque::new(),
+                missed: 0,
+            });
+        }
     }
 
-    pub fn try_pop(&self) -> Result<Option<T>, SenderDropped> {
-        if let Some(object) = self.queue.queue.lock().pop_front() {
-            Ok(Some(object))
+    pub fn is_paused(&self) -> bool {
+        self.queue.pause.lock().is_some()
+    }
+
+    /// Makes the objects buffered during the pause available and returns the number of objects
+    /// that were dropped or coalesced since the receiver was stopped or paused.
+    pub fn resume(&mut self) -> usize {
+        let mut queue_guard = self.queue.queue.lock();
+        let Some(pause) = self.queue.pause.lock().take() else {
+            return 0;
+        };
+
+        if !pause.buffer.is_empty() {
+            queue_guard.extend(pause.buffer);
+            self.queue.notify.notify_waiters();
+            self.queue.condvar.notify_all();
+        }
+        drop(queue_guard);
+
+        pause.missed
+    }
+
+    /// The error to report once the queue is empty. Closing takes precedence over dropping the
+    /// senders, so that the receivers see the reason.
+    fn empty_queue_error(&self) -> Option<RecvError> {
+        if let Some(reason) = self.receiver_queues.close_reason.lock().as_ref() {
+            Some(RecvError::Closed(reason.clone()))
         } else if self.usage_counter_watcher.is_observed_dropped() {
-            Err(SenderDropped)
+            Some(RecvError::SenderDropped)
+        } else {
+            None
+        }
+    }
+
+    pub fn try_pop(&self) -> Result<Option<T>, RecvError> {
+        // the queue stays locked while checking the senders, otherwise an object sent right
+        // before the last sender is dropped could be reported as SenderDropped
+        let mut queue_guard = self.queue.queue.lock();
+        if let Some(object) = queue_guard.pop_front() {
+            Ok(Some(object))
+        } else if let Some(error) = self.empty_queue_error() {
+            Err(error)
         } else {
             Ok(None)
         }
     }
 
-    pub async fn pop(&self) -> Result<T, SenderDropped> {
+    /// Takes every object that is in the queue, the result is empty if there is none.
+    pub fn pop_all(&self) -> Result<Vec<T>, RecvError> {
+        let mut objects = Vec::new();
+        self.drain_into(&mut objects)?;
+        Ok(objects)
+    }
+
+    /// Appends every object that is in the queue to `buffer` and returns their number.
+    pub fn drain_into(&self, buffer: &mut Vec<T>) -> Result<usize, RecvError> {
+        self.pop_many(usize::MAX, buffer)
+    }
+
+    /// Waits for at least one object, then takes at most `max` of them.
+    pub async fn recv_many(&self, max: usize) -> Result<Vec<T>, RecvError> {
+        let mut objects = Vec::new();
+        if max == 0 {
+            return Ok(objects);
+        }
+
+        loop {
+            // see pop
+            let notified = self.queue.notify.notified();
+
+            if self.pop_many(max, &mut objects)? != 0 {
+                break Ok(objects);
+            }
+
+            notified.await;
+        }
+    }
+
+    fn pop_many(&self, max: usize, buffer: &mut Vec<T>) -> Result<usize, RecvError> {
+        let mut queue_guard = self.queue.queue.lock();
+        let count = queue_guard.len().min(max);
+
+        if count != 0 {
+            buffer.extend(queue_guard.drain(..count));
+            Ok(count)
+        } else if let Some(error) = self.empty_queue_error() {
+            Err(error)
+        } else {
+            Ok(0)
+        }
+    }
+
+    pub async fn pop(&self) -> Result<T, RecvError> {
         loop {
+            // the notified future has to exist before checking the queue, otherwise an object
+            // pushed between the check and the await would not wake this receiver up
+            let notified = self.queue.notify.notified();
+
             if let Some(object) = self.try_pop()? {
                 break Ok(object);
-            } else {
-                self.queue.notify.notified().await;
             }
+
+            notified.await;
+        }
+    }
+
+    pub async fn pop_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        Ok(with_timeout(timeout, self.pop()).await??)
+    }
+
+    pub async fn pop_deadline(
+        &self,
+        deadline: tokio::time::Instant,
+    ) -> Result<T, RecvTimeoutError> {
+        Ok(with_deadline(deadline, self.pop()).await??)
+    }
+
+    /// Blocks the current thread until an object arrives, it does not need a tokio runtime.
+    pub fn recv_blocking(&self) -> Result<T, RecvError> {
+        let mut queue_guard = self.queue.queue.lock();
+        loop {
+            if let Some(object) = queue_guard.pop_front() {
+                break Ok(object);
+            } else if let Some(error) = self.empty_queue_error() {
+                break Err(error);
+            }
+
+            self.queue.condvar.wait(&mut queue_guard);
+        }
+    }
+
+    /// Same as [`Receiver::recv_blocking`], but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return Ok(self.recv_blocking()?);
+        };
+
+        let mut queue_guard = self.queue.queue.lock();
+        loop {
+            if let Some(object) = queue_guard.pop_front() {
+                break Ok(object);
+            } else if let Some(error) = self.empty_queue_error() {
+                break Err(error.into());
+            }
+
+            if self
+                .queue
+                .condvar
+                .wait_until(&mut queue_guard, deadline)
+                .timed_out()
+            {
+                break queue_guard.pop_front().ok_or(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    pub fn poll_pop(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        loop {
+            // the notified future has to exist before checking the queue, see pop
+            if self.pending_notified.is_none() {
+                self.pending_notified = Some(Box::pin(self.queue.notify.clone().notified_owned()));
+            }
+
+            match self.try_pop() {
+                Ok(Some(object)) => {
+                    self.pending_notified = None;
+                    break Poll::Ready(Ok(object));
+                }
+                Err(error) => {
+                    self.pending_notified = None;
+                    break Poll::Ready(Err(error));
+                }
+                Ok(None) => (),
+            }
+
+            let notified = self.pending_notified.as_mut().or_die();
+            if notified.as_mut().poll(cx).is_pending() {
+                break Poll::Pending;
+            }
+
+            self.pending_notified = None;
         }
     }
 
     pub fn create_receiver(&self) -> Receiver<T> {
-        let queue = ReceiverQueue::<T>::new();
-        let queue_id = self
-            .receiver_queues
-            .receiver_queues
-            .lock()
-            .create_object(queue.clone());
-        Receiver {
+        self.create_receiver_with_filter(None)
+    }
+
+    /// Creates a receiver that only gets the objects for which `filter` returns true.
+    pub fn create_filtered_receiver(
+        &self,
+        filter: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Receiver<T> {
+        self.create_receiver_with_filter(Some(Arc::new(filter)))
+    }
+
+    /// Creates a receiver that only gets the objects whose topic matches `pattern`.
+    pub fn create_topic_receiver(&self, pattern: TopicPattern) -> Receiver<T>
+    where
+        T: Topic,
+    {
+        self.create_filtered_receiver(move |object| pattern.matches(object.topic()))
+    }
+
+    fn create_receiver_with_filter(&self, filter: Option<Filter<T>>) -> Receiver<T> {
+        Receiver::new(
+            &self.receiver_queues,
+            self.usage_counter_watcher.clone(),
+            filter,
+            false,
+        )
+    }
+}
+
+impl<T> Clone for Sender<T>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
             receiver_queues: self.receiver_queues.clone(),
-            queue_id,
-            queue,
-            usage_counter_watcher: self.usage_counter_watcher.clone(),
+            usage_counter: self.usage_counter.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T>
+where
+    T: Clone,
+{
+    fn drop(&mut self) {
+        // the usage counter is released before waking up the receivers,
+        // so that they can observe that the sender is dropped
+        drop(self.usage_counter.take());
+
+        for queue in self.receiver_queues.receiver_queues.lock().iter() {
+            queue.wake_up_all();
         }
     }
 }
 
+#[cfg(feature = "futures")]
+impl<T> futures_core::Stream for Receiver<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_pop(cx).map(Result::ok)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T> futures_sink::Sink<T> for Sender<T>
+where
+    T: Clone,
+{
+    type Error = std::convert::Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.send(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 impl<T> Clone for Receiver<T>
 where
     T: Clone,
 {
     fn clone(&self) -> Self {
-        self.create_receiver()
+        // the clone gets the same filter
+        self.create_receiver_with_filter(self.queue.filter.clone())
     }
 }
 
@@ -205,10 +823,14 @@ where
             .to_be_removed
             .lock()
             .push(self.queue_id);
+
+        self.receiver_queues
+            .receiver_count
+            .send_modify(|receiver_count| *receiver_count -= 1);
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
     use super::*;
 
@@ -320,8 +942,459 @@ mod tests {
         assert_eq!(receiver0.try_pop().unwrap(), Some(7));
         assert_eq!(receiver1.try_pop().unwrap(), Some(7));
 
-        assert_eq!(receiver0.pop().await, Err(SenderDropped));
-        assert_eq!(receiver1.try_pop(), Err(SenderDropped));
-        assert_eq!(receiver2.try_pop(), Err(SenderDropped));
+        assert_eq!(receiver0.pop().await, Err(RecvError::SenderDropped));
+        assert_eq!(receiver1.try_pop(), Err(RecvError::SenderDropped));
+        assert_eq!(receiver2.try_pop(), Err(RecvError::SenderDropped));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drop_sender_while_waiting() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+
+        let task = tokio::spawn(async move { receiver.pop().await });
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        drop(sender);
+
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(2)) => {
+                panic!("timeout waiting for the receiver");
+            }
+            result = task => {
+                assert_eq!(result.unwrap(), Err(RecvError::SenderDropped));
+            }
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_and_sink() {
+        use futures::{StreamExt, stream};
+
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+
+        let collector = tokio::spawn(receiver.collect::<Vec<_>>());
+
+        stream::iter(0..5).map(Ok).forward(sender).await.unwrap();
+
+        assert_eq!(collector.await.unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn recv_blocking() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+
+        let thread = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            while let Ok(object) = receiver.recv_blocking() {
+                received.push(object);
+            }
+            received
+        });
+
+        for i in 0..100 {
+            sender.send(i);
+        }
+        drop(sender);
+
+        assert_eq!(thread.join().unwrap(), (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            sender.send(7);
+        });
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(2)), Ok(7));
+
+        thread.join().unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(2)),
+            Err(RecvTimeoutError::SenderDropped)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pop_timeout() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+
+        assert_eq!(
+            receiver.pop_timeout(Duration::from_secs(1)).await,
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        sender.send(7);
+        assert_eq!(receiver.pop_timeout(Duration::from_secs(1)).await, Ok(7));
+
+        // too large for a deadline, so it waits without one
+        sender.send(8);
+        assert_eq!(receiver.pop_timeout(Duration::MAX).await, Ok(8));
+
+        drop(sender);
+        assert_eq!(
+            receiver
+                .pop_deadline(tokio::time::Instant::now() + Duration::from_secs(1))
+                .await,
+            Err(RecvTimeoutError::SenderDropped)
+        );
+    }
+
+    #[test]
+    fn filtered_receiver() {
+        let sender = Sender::<usize>::new();
+
+        let all = sender.create_receiver();
+        let even = sender.create_filtered_receiver(|value| value % 2 == 0);
+        let even_clone = even.clone();
+
+        for i in 0..5 {
+            sender.send(i);
+        }
+
+        for i in 0..5 {
+            assert_eq!(all.try_pop().unwrap(), Some(i));
+        }
+        for receiver in [&even, &even_clone] {
+            assert_eq!(receiver.try_pop().unwrap(), Some(0));
+            assert_eq!(receiver.try_pop().unwrap(), Some(2));
+            assert_eq!(receiver.try_pop().unwrap(), Some(4));
+            assert_eq!(receiver.try_pop().unwrap(), None);
+        }
+
+        sender.send_directly(7, &even);
+        assert_eq!(even.try_pop().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn topic_receiver() {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        struct Event {
+            topic: String,
+        }
+
+        impl Topic for Event {
+            fn topic(&self) -> &str {
+                &self.topic
+            }
+        }
+
+        let event = |topic: &str| Event {
+            topic: topic.to_string(),
+        };
+
+        let sender = Sender::<Event>::new();
+
+        let positions =
+            sender.create_topic_receiver(TopicPattern::new("entity/+/position").unwrap());
+        let entity_42 = sender.create_topic_receiver(TopicPattern::new("entity/42/#").unwrap());
+
+        sender.send(event("entity/42/position"));
+        sender.send(event("entity/7/position"));
+        sender.send(event("entity/42/velocity"));
+
+        assert_eq!(
+            positions.try_pop().unwrap(),
+            Some(event("entity/42/position"))
+        );
+        assert_eq!(
+            positions.try_pop().unwrap(),
+            Some(event("entity/7/position"))
+        );
+        assert_eq!(positions.try_pop().unwrap(), None);
+
+        assert_eq!(
+            entity_42.try_pop().unwrap(),
+            Some(event("entity/42/position"))
+        );
+        assert_eq!(
+            entity_42.try_pop().unwrap(),
+            Some(event("entity/42/velocity"))
+        );
+        assert_eq!(entity_42.try_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn replay_last_n() {
+        let sender = Sender::<usize>::with_history(History::last_n(3));
+
+        for i in 0..5 {
+            sender.send(i);
+        }
+
+        let receiver = sender.create_replaying_receiver();
+        let even = sender.create_replaying_filtered_receiver(|value| value % 2 == 0);
+        let late = sender.create_receiver();
+        sender.send(5);
+
+        for i in 2..6 {
+            assert_eq!(receiver.try_pop().unwrap(), Some(i));
+        }
+        assert_eq!(receiver.try_pop().unwrap(), None);
+
+        assert_eq!(even.try_pop().unwrap(), Some(2));
+        assert_eq!(even.try_pop().unwrap(), Some(4));
+        assert_eq!(even.try_pop().unwrap(), None);
+
+        assert_eq!(late.try_pop().unwrap(), Some(5));
+        assert_eq!(late.try_pop().unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replay_max_age() {
+        let sender = Sender::<usize>::with_history(History::max_age(Duration::from_secs(10)));
+
+        sender.send(0);
+        tokio::time::advance(Duration::from_secs(6)).await;
+        sender.send(1);
+        tokio::time::advance(Duration::from_secs(6)).await;
+
+        let receiver = sender.create_replaying_receiver();
+        assert_eq!(receiver.try_pop().unwrap(), Some(1));
+        assert_eq!(receiver.try_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn replay_last_per_key() {
+        let sender = Sender::<(&str, usize)>::with_history(History::last_per_key(|(key, _)| *key));
+
+        sender.send(("a", 0));
+        sender.send(("b", 1));
+        sender.send(("a", 2));
+        sender.send(("c", 3));
+
+        let receiver = sender.create_replaying_receiver();
+        assert_eq!(receiver.try_pop().unwrap(), Some(("b", 1)));
+        assert_eq!(receiver.try_pop().unwrap(), Some(("a", 2)));
+        assert_eq!(receiver.try_pop().unwrap(), Some(("c", 3)));
+        assert_eq!(receiver.try_pop().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn batches() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+        let odd = sender.create_filtered_receiver(|value| value % 2 == 1);
+
+        sender.send_batch(0..5);
+
+        assert_eq!(receiver.recv_many(2).await, Ok(vec![0, 1]));
+        let mut buffer = vec![7];
+        assert_eq!(receiver.drain_into(&mut buffer), Ok(3));
+        assert_eq!(buffer, vec![7, 2, 3, 4]);
+        assert_eq!(receiver.pop_all(), Ok(vec![]));
+
+        assert_eq!(odd.pop_all(), Ok(vec![1, 3]));
+
+        drop(sender);
+        assert_eq!(receiver.recv_many(2).await, Err(RecvError::SenderDropped));
+        assert_eq!(receiver.pop_all(), Err(RecvError::SenderDropped));
+    }
+
+    #[test]
+    fn pause_with_buffer() {
+        let sender = Sender::<usize>::new();
+        let mut receiver = sender.create_receiver();
+
+        sender.send(0);
+        receiver.pause(PauseMode::Buffer { limit: 2 });
+        assert!(receiver.is_paused());
+
+        sender.send_batch(1..5);
+        assert_eq!(receiver.pop_all(), Ok(vec![0]));
+
+        assert_eq!(receiver.resume(), 2);
+        assert!(!receiver.is_paused());
+        assert_eq!(receiver.pop_all(), Ok(vec![3, 4]));
+
+        receiver.stop();
+        sender.send(5);
+        assert_eq!(receiver.resume(), 1);
+        assert_eq!(receiver.resume(), 0);
+        assert_eq!(receiver.pop_all(), Ok(vec![]));
+    }
+
+    #[test]
+    fn pause_keep_latest() {
+        let sender = Sender::<usize>::new();
+        let mut receiver = sender.create_receiver();
+
+        receiver.pause(PauseMode::KeepLatest);
+        for i in 0..5 {
+            sender.send(i);
+        }
+
+        assert_eq!(receiver.resume(), 4);
+        assert_eq!(receiver.pop_all(), Ok(vec![4]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn receiver_lifecycle() {
+        let sender = Sender::<usize>::new();
+        assert_eq!(sender.receiver_count(), 0);
+
+        let waiter = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.wait_for_receiver().await }
+        });
+
+        let receiver0 = sender.create_receiver();
+        let receiver1 = receiver0.clone();
+        assert_eq!(sender.receiver_count(), 2);
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(2)) => {
+                panic!("timeout waiting for a receiver");
+            }
+            result = waiter => result.unwrap(),
+        }
+
+        sender.send(0);
+        sender.send(1);
+        assert_eq!(receiver0.try_pop(), Ok(Some(0)));
+        assert_eq!(receiver0.len(), 1);
+
+        let mut queue_lengths = sender.queue_lengths();
+        queue_lengths.sort();
+        assert_eq!(queue_lengths, vec![1, 2]);
+
+        let closed = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.closed().await }
+        });
+
+        drop(receiver0);
+        assert_eq!(sender.receiver_count(), 1);
+        assert_eq!(sender.queue_lengths(), vec![2]);
+
+        drop(receiver1);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(2)) => {
+                panic!("timeout waiting for the receivers to be dropped");
+            }
+            result = closed => result.unwrap(),
+        }
+        assert_eq!(sender.receiver_count(), 0);
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Shutdown {
+        Requested,
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+        let other_sender = sender.clone();
+
+        sender.send_batch([0, 1]);
+        sender.close(Shutdown::Requested);
+        other_sender.send(2);
+        other_sender.close("ignored");
+
+        assert!(other_sender.is_closed());
+        assert_eq!(receiver.pop().await, Ok(0));
+        assert_eq!(receiver.recv_blocking(), Ok(1));
+
+        let Err(RecvError::Closed(reason)) = receiver.pop().await else {
+            panic!("the receiver is not closed");
+        };
+        assert_eq!(reason.downcast_ref(), Some(&Shutdown::Requested));
+        assert!(!reason.is::<&str>());
+
+        assert_eq!(receiver.try_pop(), Err(RecvError::Closed(reason.clone())));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Closed(reason))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn close_while_waiting() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+        let blocking_receiver = sender.create_receiver();
+
+        let task = tokio::spawn(async move { receiver.pop().await });
+        let thread = std::thread::spawn(move || blocking_receiver.recv_blocking());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sender.close(Shutdown::Requested);
+
+        let result = tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(RecvError::Closed(reason)) if reason.is::<Shutdown>()));
+        assert!(matches!(
+            thread.join().unwrap(),
+            Err(RecvError::Closed(reason)) if reason.is::<Shutdown>()
+        ));
+    }
+
+    #[test]
+    fn weak_sender() {
+        let sender = Sender::<usize>::new();
+        let receiver = sender.create_receiver();
+        let weak_sender = sender.downgrade();
+
+        weak_sender.upgrade().unwrap().send(0);
+        drop(sender);
+
+        // the weak sender does not keep the receiver alive
+        assert_eq!(receiver.try_pop(), Ok(Some(0)));
+        assert_eq!(receiver.try_pop(), Err(RecvError::SenderDropped));
+        assert!(weak_sender.upgrade().is_none());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{future::block_on, thread};
+
+    use super::{RecvError, Sender};
+
+    #[test]
+    fn sender_drop_wakes_up_blocked_receiver() {
+        loom::model(|| {
+            let sender = Sender::<usize>::new();
+            let receiver = sender.create_receiver();
+
+            let sender_thread = thread::spawn(move || {
+                sender.send(0);
+                drop(sender);
+            });
+
+            assert_eq!(receiver.recv_blocking(), Ok(0));
+            assert_eq!(receiver.recv_blocking(), Err(RecvError::SenderDropped));
+            sender_thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn async_receiver_does_not_miss_an_object() {
+        loom::model(|| {
+            let sender = Sender::<usize>::new();
+            let receiver = sender.create_receiver();
+
+            let sender_thread = thread::spawn(move || sender.send(0));
+
+            assert_eq!(block_on(receiver.pop()), Ok(0));
+            sender_thread.join().unwrap();
+        });
     }
 }
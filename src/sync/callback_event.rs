@@ -1,6 +1,9 @@
 use crate::containers::object_pool::{ObjectPool, ObjectPoolIndex};
 
-use super::types::{ArcMutex, arc_mutex_new};
+use super::{
+    types::{ArcMutex, arc_mutex_new},
+    usage_counter::{UsageCounter, UsageCounterWatcher},
+};
 
 type BoxedCallback<T> = Box<dyn FnMut(&T) + Send>;
 
@@ -9,9 +12,33 @@ pub struct Subscription<T> {
     callbacks: ArcMutex<ObjectPool<BoxedCallback<T>>>,
 }
 
-#[derive(Clone)]
 pub struct Sender<T> {
     callbacks: ArcMutex<ObjectPool<BoxedCallback<T>>>,
+    usage_counter: UsageCounter,
+}
+
+/// A sender that can only trigger while at least one [`Sender`] is alive.
+pub struct WeakSender<T> {
+    callbacks: ArcMutex<ObjectPool<BoxedCallback<T>>>,
+    usage_counter_watcher: UsageCounterWatcher,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            callbacks: self.callbacks.clone(),
+            usage_counter: self.usage_counter.clone(),
+        }
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            callbacks: self.callbacks.clone(),
+            usage_counter_watcher: self.usage_counter_watcher.clone(),
+        }
+    }
 }
 
 pub struct Subscriber<T> {
@@ -28,6 +55,7 @@ impl<T> Sender<T> {
     pub fn new() -> Self {
         Self {
             callbacks: arc_mutex_new(ObjectPool::new()),
+            usage_counter: UsageCounter::new(),
         }
     }
 
@@ -42,6 +70,25 @@ impl<T> Sender<T> {
             (f)(obj);
         }
     }
+
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender {
+            callbacks: self.callbacks.clone(),
+            usage_counter_watcher: self.usage_counter.watcher(),
+        }
+    }
+}
+
+impl<T> WeakSender<T> {
+    /// Returns None if every sender was already dropped.
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        self.usage_counter_watcher
+            .upgrade()
+            .map(|usage_counter| Sender {
+                callbacks: self.callbacks.clone(),
+                usage_counter,
+            })
+    }
 }
 
 impl<T> Subscriber<T> {
@@ -62,7 +109,7 @@ impl<T> Drop for Subscription<T> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
     use super::*;
 
@@ -137,4 +184,24 @@ mod tests {
         }
         assert_eq!(counter.load(Ordering::Relaxed), 0);
     }
+
+    #[test]
+    fn weak_sender() {
+        let sender = Sender::<usize>::new();
+        let subscriber = sender.create_subscriber();
+        let weak_sender = sender.downgrade();
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        let _subscription = subscriber.subscribe(move |obj_ref| {
+            counter_clone.fetch_add(*obj_ref, Ordering::Relaxed);
+        });
+
+        weak_sender.upgrade().unwrap().trigger(&1);
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+
+        // the subscriber does not keep the weak sender usable
+        drop(sender);
+        assert!(weak_sender.upgrade().is_none());
+    }
 }
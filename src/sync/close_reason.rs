@@ -0,0 +1,58 @@
+//! # Close Reason
+//!
+//! A user defined value that tells the receivers of a channel why it was closed explicitly, e.g.
+//! a shutdown or a failure upstream.
+
+use std::{
+    any::{Any, type_name},
+    fmt::Debug,
+    sync::Arc,
+};
+
+use crate::cast::DowncastArc;
+
+#[derive(Clone)]
+pub struct CloseReason {
+    reason: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+}
+
+impl CloseReason {
+    pub fn new<Reason: Any + Send + Sync>(reason: Reason) -> Self {
+        Self {
+            reason: Arc::new(reason),
+            type_name: type_name::<Reason>(),
+        }
+    }
+
+    pub fn is<Reason: Any>(&self) -> bool {
+        self.reason.is::<Reason>()
+    }
+
+    pub fn downcast_ref<Reason: Any>(&self) -> Option<&Reason> {
+        self.reason.downcast_ref()
+    }
+
+    pub fn downcast<Reason: Any>(&self) -> Option<Arc<Reason>> {
+        self.reason.downcast_arc()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl Debug for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CloseReason({})", self.type_name)
+    }
+}
+
+/// Two reasons are equal if they come from the same `close` call.
+impl PartialEq for CloseReason {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.reason, &other.reason)
+    }
+}
+
+impl Eq for CloseReason {}
@@ -0,0 +1,187 @@
+//! # Delayed Multi Producer, Collective Consumer
+//!
+//! An [`mpcc`](super::mpcc) channel where a message becomes visible to the receivers only at a
+//! given time. The messages are received in the order of their due time, the ones due at the
+//! same time in the order they were sent.
+//!
+//! The time is measured with the tokio clock, so the channel follows a paused clock in tests.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio::time::Instant;
+
+use super::mpcc::{MessageQueue, QueueReceiver, QueueSender, SendError, channel_with_queue};
+
+// the due time of a delay that does not fit into an Instant, the same as tokio's far future
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+pub type Sender<T> = QueueSender<DelayedQueue<T>>;
+pub type Receiver<T> = QueueReceiver<DelayedQueue<T>>;
+
+pub struct DelayedQueue<T> {
+    msgs: BTreeMap<(Instant, u64), T>,
+    next_sequence: u64,
+}
+
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    channel_with_queue(DelayedQueue::new())
+}
+
+impl<T> Default for DelayedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> DelayedQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            msgs: BTreeMap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl<T: Send> MessageQueue for DelayedQueue<T> {
+    type Input = (Instant, T);
+    type Output = T;
+
+    fn push(&mut self, (due, msg): (Instant, T)) {
+        self.msgs.insert((due, self.next_sequence), msg);
+        self.next_sequence += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let entry = self.msgs.first_entry()?;
+        if entry.key().0 <= Instant::now() {
+            Some(entry.remove())
+        } else {
+            None
+        }
+    }
+
+    /// Also counts the messages that are not due yet.
+    fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    fn clear(&mut self) {
+        self.msgs.clear();
+    }
+
+    fn next_wakeup(&self) -> Option<Instant> {
+        self.msgs.first_key_value().map(|((due, _), _)| *due)
+    }
+}
+
+impl<T: Send> QueueSender<DelayedQueue<T>> {
+    pub fn send_at(&self, msg: T, due: Instant) -> Result<(), SendError> {
+        self.send((due, msg))
+    }
+
+    /// A delay that is too large for the clock, e.g. [`Duration::MAX`], is due in 30 years.
+    pub fn send_after(&self, msg: T, delay: Duration) -> Result<(), SendError> {
+        let now = Instant::now();
+        let due = now.checked_add(delay).unwrap_or(now + FAR_FUTURE);
+        self.send_at(msg, due)
+    }
+
+    /// Sends a message that is visible right away.
+    pub fn send_now(&self, msg: T) -> Result<(), SendError> {
+        self.send_at(msg, Instant::now())
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::{Instant, advance};
+
+    use super::channel;
+    use crate::sync::mpcc::{RecvError, TryRecvError};
+
+    #[tokio::test(start_paused = true)]
+    async fn delayed_order() {
+        let (sender, receiver) = channel::<&str>();
+        let start = Instant::now();
+
+        sender.send_after("late", Duration::from_secs(10)).unwrap();
+        sender.send_after("early", Duration::from_secs(5)).unwrap();
+        sender
+            .send_at("also early", start + Duration::from_secs(5))
+            .unwrap();
+        sender.send_now("now").unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), "now");
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        advance(Duration::from_secs(5)).await;
+        assert_eq!(receiver.pop_all().unwrap(), vec!["early", "also early"]);
+
+        advance(Duration::from_secs(4)).await;
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(receiver.try_recv().unwrap(), "late");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_due_message() {
+        let (sender, mut receiver) = channel::<usize>();
+        let start = Instant::now();
+
+        sender.send_after(7, Duration::from_secs(60)).unwrap();
+
+        // the paused clock jumps to the due time of the message
+        assert_eq!(receiver.recv_async().await.unwrap(), 7);
+        assert!(Instant::now() - start >= Duration::from_secs(60));
+
+        // a message sent sooner wakes up the receiver before the pending one is due
+        let waiter = tokio::spawn({
+            let mut receiver = receiver.clone();
+            async move { receiver.recv_async().await.unwrap() }
+        });
+        sender.send_after(1, Duration::from_secs(60)).unwrap();
+        tokio::task::yield_now().await;
+        sender.send_after(0, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(waiter.await.unwrap(), 0);
+        assert_eq!(receiver.recv_async().await.unwrap(), 1);
+
+        drop(sender);
+        assert!(matches!(
+            receiver.recv_async().await,
+            Err(RecvError::Disconnected)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delay_too_large_for_the_clock() {
+        let (sender, receiver) = channel::<&str>();
+
+        sender.send_after("never", Duration::MAX).unwrap();
+        sender.send_now("now").unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), "now");
+
+        advance(Duration::from_secs(86400 * 365)).await;
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        assert_eq!(receiver.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_messages_keep_receivers_connected() {
+        let (sender, mut receiver) = channel::<usize>();
+
+        sender.send_after(7, Duration::from_secs(60)).unwrap();
+        drop(sender);
+
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        assert_eq!(receiver.recv_async().await.unwrap(), 7);
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+    }
+}
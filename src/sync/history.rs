@@ -0,0 +1,160 @@
+//! # History
+//!
+//! Keeps the recently sent messages of a [`broadcast::Sender`](super::broadcast::Sender), so that
+//! late joining receivers can replay them.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+trait HistoryBuffer<T>: Send {
+    fn record(&mut self, object: &T);
+    fn replay(&mut self) -> Vec<T>;
+}
+
+pub struct History<T> {
+    buffer: Box<dyn HistoryBuffer<T>>,
+}
+
+struct LastN<T> {
+    count: usize,
+    objects: VecDeque<T>,
+}
+
+struct MaxAge<T> {
+    max_age: Duration,
+    objects: VecDeque<(Instant, T)>,
+}
+
+struct LastPerKey<T, K, F> {
+    key_fn: F,
+    next_sequence: u64,
+    objects: BTreeMap<K, (u64, T)>,
+}
+
+impl<T> History<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Keeps the last `count` messages.
+    pub fn last_n(count: usize) -> Self {
+        Self {
+            buffer: Box::new(LastN {
+                count,
+                objects: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Keeps the messages that are not older than `max_age`. The age is measured with the tokio
+    /// clock, so it follows a paused clock in tests.
+    pub fn max_age(max_age: Duration) -> Self {
+        Self {
+            buffer: Box::new(MaxAge {
+                max_age,
+                objects: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Keeps only the last message for every key, replayed in the order they were sent.
+    pub fn last_per_key<K, F>(key_fn: F) -> Self
+    where
+        K: Ord + Send + 'static,
+        F: Fn(&T) -> K + Send + 'static,
+    {
+        Self {
+            buffer: Box::new(LastPerKey {
+                key_fn,
+                next_sequence: 0,
+                objects: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl<T> History<T> {
+    pub(crate) fn record(&mut self, object: &T) {
+        self.buffer.record(object);
+    }
+
+    pub(crate) fn replay(&mut self) -> Vec<T> {
+        self.buffer.replay()
+    }
+}
+
+impl<T> HistoryBuffer<T> for LastN<T>
+where
+    T: Clone + Send,
+{
+    fn record(&mut self, object: &T) {
+        if self.count == 0 {
+            return;
+        }
+
+        if self.objects.len() == self.count {
+            self.objects.pop_front();
+        }
+        self.objects.push_back(object.clone());
+    }
+
+    fn replay(&mut self) -> Vec<T> {
+        self.objects.iter().cloned().collect()
+    }
+}
+
+impl<T> MaxAge<T> {
+    fn forget_old_objects(&mut self, now: Instant) {
+        while self
+            .objects
+            .front()
+            .is_some_and(|(sent_at, _)| now.duration_since(*sent_at) > self.max_age)
+        {
+            self.objects.pop_front();
+        }
+    }
+}
+
+impl<T> HistoryBuffer<T> for MaxAge<T>
+where
+    T: Clone + Send,
+{
+    fn record(&mut self, object: &T) {
+        let now = Instant::now();
+        self.forget_old_objects(now);
+        self.objects.push_back((now, object.clone()));
+    }
+
+    fn replay(&mut self) -> Vec<T> {
+        self.forget_old_objects(Instant::now());
+        self.objects
+            .iter()
+            .map(|(_, object)| object.clone())
+            .collect()
+    }
+}
+
+impl<T, K, F> HistoryBuffer<T> for LastPerKey<T, K, F>
+where
+    T: Clone + Send,
+    K: Ord + Send,
+    F: Fn(&T) -> K + Send,
+{
+    fn record(&mut self, object: &T) {
+        self.objects
+            .insert((self.key_fn)(object), (self.next_sequence, object.clone()));
+        self.next_sequence += 1;
+    }
+
+    fn replay(&mut self) -> Vec<T> {
+        let mut objects: Vec<_> = self.objects.values().collect();
+        objects.sort_by_key(|(sequence, _)| *sequence);
+        objects
+            .into_iter()
+            .map(|(_, object)| object.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,149 @@
+//! # Keyed Multi Producer, Collective Consumer
+//!
+//! An [`mpcc`](super::mpcc) channel where at most one message is pending for every key. Sending a
+//! message with a key that is already pending replaces the pending message, or merges into it
+//! with a user defined function, and the pending message keeps its place in the queue. Once a
+//! message is received, the next one with the same key is queued again.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use super::mpcc::{MessageQueue, QueueReceiver, QueueSender, SendError, channel_with_queue};
+
+type MergeFn<T> = Box<dyn FnMut(&mut T, T) + Send>;
+
+pub type Sender<K, T> = QueueSender<KeyedQueue<K, T>>;
+pub type Receiver<K, T> = QueueReceiver<KeyedQueue<K, T>>;
+
+pub struct KeyedQueue<K, T> {
+    keys: VecDeque<K>,
+    msgs: BTreeMap<K, T>,
+    merge: MergeFn<T>,
+}
+
+/// The pending message of a key is replaced by the new one.
+pub fn channel<K, T>() -> (Sender<K, T>, Receiver<K, T>)
+where
+    K: Ord + Clone + Send + 'static,
+    T: Send + 'static,
+{
+    channel_with_queue(KeyedQueue::new())
+}
+
+/// The new message of a key is merged into the pending one by `merge`.
+pub fn channel_with_merge<K, T>(
+    merge: impl FnMut(&mut T, T) + Send + 'static,
+) -> (Sender<K, T>, Receiver<K, T>)
+where
+    K: Ord + Clone + Send + 'static,
+    T: Send + 'static,
+{
+    channel_with_queue(KeyedQueue::with_merge(merge))
+}
+
+impl<K, T> Default for KeyedQueue<K, T>
+where
+    T: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T> KeyedQueue<K, T>
+where
+    T: 'static,
+{
+    pub fn new() -> Self {
+        Self::with_merge(|pending, msg| *pending = msg)
+    }
+
+    pub fn with_merge(merge: impl FnMut(&mut T, T) + Send + 'static) -> Self {
+        Self {
+            keys: VecDeque::new(),
+            msgs: BTreeMap::new(),
+            merge: Box::new(merge),
+        }
+    }
+}
+
+impl<K, T> MessageQueue for KeyedQueue<K, T>
+where
+    K: Ord + Clone + Send,
+    T: Send,
+{
+    type Input = (K, T);
+    type Output = (K, T);
+
+    fn push(&mut self, (key, msg): (K, T)) {
+        if let Some(pending) = self.msgs.get_mut(&key) {
+            (self.merge)(pending, msg);
+        } else {
+            self.keys.push_back(key.clone());
+            self.msgs.insert(key, msg);
+        }
+    }
+
+    fn pop(&mut self) -> Option<(K, T)> {
+        let key = self.keys.pop_front()?;
+        let msg = self.msgs.remove(&key)?;
+        Some((key, msg))
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.msgs.clear();
+    }
+}
+
+impl<K, T> QueueSender<KeyedQueue<K, T>>
+where
+    K: Ord + Clone + Send,
+    T: Send,
+{
+    pub fn send_keyed(&self, key: K, msg: T) -> Result<(), SendError> {
+        self.send((key, msg))
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{channel, channel_with_merge};
+
+    #[test]
+    fn replace() {
+        let (sender, receiver) = channel::<&str, usize>();
+
+        sender.send_keyed("a", 0).unwrap();
+        sender.send_keyed("b", 1).unwrap();
+        sender.send_keyed("a", 2).unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), ("a", 2));
+
+        // "a" is not pending anymore, so it is queued again
+        sender.send_keyed("a", 3).unwrap();
+        sender.send_keyed("b", 4).unwrap();
+
+        assert_eq!(receiver.pop_all().unwrap(), vec![("b", 4), ("a", 3)]);
+    }
+
+    #[test]
+    fn merge() {
+        let (sender, receiver) =
+            channel_with_merge::<&str, Vec<usize>>(|pending, mut msg| pending.append(&mut msg));
+
+        sender.send_keyed("a", vec![0]).unwrap();
+        sender.send_keyed("b", vec![1]).unwrap();
+        sender
+            .send_batch([("a", vec![2]), ("a", vec![3, 4])])
+            .unwrap();
+
+        assert_eq!(
+            receiver.pop_all().unwrap(),
+            vec![("a", vec![0, 2, 3, 4]), ("b", vec![1])]
+        );
+    }
+}
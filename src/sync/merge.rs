@@ -0,0 +1,273 @@
+//! # Merge
+//!
+//! Receives from several [`broadcast`] and [`mpcc`](super::mpcc) receivers at once. Sources of
+//! different types can be merged by mapping their items into an enum:
+//!
+//! ```
+//! use bytifex_utils::sync::{broadcast, merge::Merge, mpcc};
+//!
+//! enum Event {
+//!     Tick(u64),
+//!     Job(String),
+//! }
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let ticks = broadcast::Sender::<u64>::new();
+//! let (jobs, job_receiver) = mpcc::channel::<String>();
+//!
+//! let mut merge = Merge::new();
+//! let tick_source = merge.add_with(ticks.create_receiver(), Event::Tick);
+//! merge.add_with(job_receiver, Event::Job);
+//!
+//! ticks.send(1);
+//! let (source, event) = merge.next().await.unwrap();
+//! assert_eq!(source, tick_source);
+//! assert!(matches!(event, Event::Tick(1)));
+//! # });
+//! ```
+//!
+//! The sources are polled in turns, so a busy source does not starve the others. A closed or
+//! disconnected source is removed, the others are still received from.
+
+use std::{
+    future::poll_fn,
+    task::{Context, Poll},
+};
+
+use super::{
+    broadcast,
+    mpcc::{MessageQueue, QueueReceiver},
+};
+
+/// A receiver that can be added to a [`Merge`].
+pub trait Source: Send {
+    type Item;
+
+    /// Returns `Ready(None)` once the source cannot produce any more items.
+    fn poll_source(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+
+/// Identifies the source of a merged item, it is returned when the source is added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+type PollSource<T> = Box<dyn FnMut(&mut Context<'_>) -> Poll<Option<T>> + Send>;
+
+pub struct Merge<T> {
+    // indexed by SourceId, None after the source is closed or removed
+    sources: Vec<Option<PollSource<T>>>,
+    // the source that is polled first next time
+    next_source: usize,
+}
+
+impl<T> Default for Merge<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Merge<T> {
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            next_source: 0,
+        }
+    }
+
+    pub fn add<S>(&mut self, source: S) -> SourceId
+    where
+        S: Source<Item = T> + 'static,
+    {
+        self.add_with(source, |item| item)
+    }
+
+    /// Adds a source whose items are converted by `map`, e.g. into a variant of an enum.
+    pub fn add_with<S>(
+        &mut self,
+        mut source: S,
+        map: impl Fn(S::Item) -> T + Send + 'static,
+    ) -> SourceId
+    where
+        S: Source + 'static,
+    {
+        self.sources.push(Some(Box::new(move |cx| {
+            source.poll_source(cx).map(|item| item.map(&map))
+        })));
+        SourceId(self.sources.len() - 1)
+    }
+
+    /// Drops the source, returns false if it was already closed or removed.
+    pub fn remove(&mut self, source_id: SourceId) -> bool {
+        self.sources
+            .get_mut(source_id.0)
+            .and_then(Option::take)
+            .is_some()
+    }
+
+    pub fn is_open(&self, source_id: SourceId) -> bool {
+        self.sources.get(source_id.0).is_some_and(Option::is_some)
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.sources.iter().flatten().count()
+    }
+
+    /// Returns None when every source is closed.
+    pub async fn next(&mut self) -> Option<(SourceId, T)> {
+        poll_fn(|cx| self.poll_next_item(cx)).await
+    }
+
+    pub fn poll_next_item(&mut self, cx: &mut Context<'_>) -> Poll<Option<(SourceId, T)>> {
+        let source_count = self.sources.len();
+        for offset in 0..source_count {
+            let index = (self.next_source + offset) % source_count;
+            let Some(source) = self.sources[index].as_mut() else {
+                continue;
+            };
+
+            match source(cx) {
+                Poll::Ready(Some(item)) => {
+                    self.next_source = (index + 1) % source_count;
+                    return Poll::Ready(Some((SourceId(index), item)));
+                }
+                Poll::Ready(None) => self.sources[index] = None,
+                Poll::Pending => (),
+            }
+        }
+
+        if self.open_count() == 0 {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Source for broadcast::Receiver<T>
+where
+    T: Clone + Send + 'static,
+{
+    type Item = T;
+
+    fn poll_source(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_pop(cx).map(Result::ok)
+    }
+}
+
+impl<Q: MessageQueue> Source for QueueReceiver<Q> {
+    type Item = Q::Output;
+
+    fn poll_source(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx).map(Result::ok)
+    }
+}
+
+impl<T> Source for Merge<T>
+where
+    T: Send,
+{
+    type Item = (SourceId, T);
+
+    fn poll_source(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_next_item(cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T> futures_core::Stream for Merge<T> {
+    type Item = (SourceId, T);
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_item(cx)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use super::Merge;
+    use crate::sync::{broadcast, mpcc};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Number(usize),
+        Text(String),
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn heterogeneous_sources() {
+        let numbers = broadcast::Sender::<usize>::new();
+        let (texts, text_receiver) = mpcc::channel::<String>();
+
+        let mut merge = Merge::new();
+        let number_source = merge.add_with(numbers.create_receiver(), Event::Number);
+        let text_source = merge.add_with(text_receiver, Event::Text);
+
+        tokio::spawn({
+            let texts = texts.clone();
+            async move { texts.send("0".to_string()).unwrap() }
+        });
+        let received = tokio::time::timeout(Duration::from_secs(2), merge.next())
+            .await
+            .unwrap();
+        assert_eq!(received, Some((text_source, Event::Text("0".to_string()))));
+
+        numbers.send(1);
+        assert_eq!(merge.next().await, Some((number_source, Event::Number(1))));
+    }
+
+    #[tokio::test]
+    async fn fair_polling() {
+        let (busy, busy_receiver) = mpcc::channel::<usize>();
+        let (quiet, quiet_receiver) = mpcc::channel::<usize>();
+
+        let mut merge = Merge::new();
+        let busy_source = merge.add(busy_receiver);
+        let quiet_source = merge.add(quiet_receiver);
+
+        busy.send_batch(0..10).unwrap();
+        quiet.send_batch([100, 101]).unwrap();
+
+        let mut sources = Vec::new();
+        for _ in 0..6 {
+            sources.push(merge.next().await.unwrap().0);
+        }
+        assert_eq!(
+            sources,
+            [
+                busy_source,
+                quiet_source,
+                busy_source,
+                quiet_source,
+                busy_source,
+                busy_source
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn continue_after_a_source_closes() {
+        let first = broadcast::Sender::<usize>::new();
+        let second = broadcast::Sender::<usize>::new();
+
+        let mut merge = Merge::new();
+        let first_source = merge.add(first.create_receiver());
+        let second_source = merge.add(second.create_receiver());
+
+        first.send(0);
+        first.close("done");
+        assert_eq!(merge.next().await, Some((first_source, 0)));
+
+        second.send_batch([1, 2]);
+        assert_eq!(merge.next().await, Some((second_source, 1)));
+
+        // the first source is polled before the second one, and it is found closed
+        assert_eq!(merge.next().await, Some((second_source, 2)));
+        assert!(!merge.is_open(first_source));
+        assert_eq!(merge.open_count(), 1);
+
+        drop(second);
+        assert_eq!(merge.next().await, None);
+    }
+}
@@ -1,8 +1,21 @@
+pub mod ack_mpcc;
 pub mod app_loop_state;
 pub mod async_item;
 pub mod broadcast;
 pub mod callback_event;
+pub mod close_reason;
+pub mod delayed_mpcc;
+pub mod history;
+pub mod keyed_mpcc;
+pub mod merge;
 pub mod mpcc;
 pub mod observable_fn;
+pub(crate) mod primitives;
+pub mod priority_mpcc;
+pub mod rpc;
+pub mod shared_broadcast;
+pub mod timeout;
+pub mod topic;
 pub mod types;
 pub mod usage_counter;
+pub mod worker_pool;
@@ -1,141 +1,664 @@
 //! # Multi Producer, Collective Consumer
 //!
 //! Every message sent throught the channel is received only once by any Consumer
+//!
+//! The pending messages are stored in a [`MessageQueue`], the plain [`Sender`] and [`Receiver`]
+//! use a FIFO [`VecDeque`]. Other queues can be used through [`channel_with_queue`].
 
 use std::{
+    any::Any,
     collections::VecDeque,
-    sync::{
-        Arc,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::watch, time::timeout_at};
+
+use super::{
+    close_reason::CloseReason,
+    primitives::{
+        Condvar, Mutex,
         atomic::{self, AtomicUsize},
     },
+    timeout::{Timeout, with_deadline, with_timeout},
+    types::{ArcMutex, arc_mutex_new},
 };
 
-use tokio::sync::watch;
+type PendingChange =
+    Pin<Box<dyn Future<Output = Result<(), watch::error::RecvError>> + Send + 'static>>;
+
+/// Stores the pending messages of a channel, it is always accessed while the channel is locked.
+pub trait MessageQueue: Send {
+    /// What the senders send.
+    type Input: Send;
+    /// What the receivers get.
+    type Output: Send;
+
+    fn push(&mut self, input: Self::Input);
+
+    /// Takes the next message that can be received right now.
+    fn pop(&mut self) -> Option<Self::Output>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self);
 
-use super::types::{ArcMutex, arc_mutex_new};
+    /// The time when a message becomes available without being sent, e.g. a delayed one.
+    fn next_wakeup(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+}
 
 #[derive(Debug)]
 pub enum SendError {
     Disconnected,
+    Closed(CloseReason),
 }
 
 #[derive(Debug)]
 pub enum RecvError {
     Disconnected,
+    Closed(CloseReason),
+}
+
+#[derive(Debug)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+    Closed(CloseReason),
 }
 
 #[derive(Debug)]
 pub enum TryRecvError {
     Empty,
     Disconnected,
+    Closed(CloseReason),
 }
 
-struct Shared<T: Send> {
-    queue: ArcMutex<VecDeque<T>>,
+struct Shared<Q: MessageQueue> {
+    queue: ArcMutex<Q>,
     sender_count: Arc<AtomicUsize>,
+    // the receivers of the queue watcher also include the clones used while waiting
+    receiver_count: Arc<watch::Sender<usize>>,
     // todo!("use an async condvar")
     queue_watcher_sender: Arc<watch::Sender<()>>,
+    // wakes up the threads blocked on the queue, it is always used with the queue's mutex
+    queue_condvar: Arc<Condvar>,
+    close_reason: ArcMutex<Option<CloseReason>>,
 }
 
-pub struct Sender<T: Send> {
-    shared: Shared<T>,
+pub struct QueueSender<Q: MessageQueue> {
+    shared: Shared<Q>,
 }
 
-pub struct Receiver<T: Send> {
-    shared: Shared<T>,
+/// A sender that does not keep the receivers connected, see [`QueueSender::downgrade`].
+pub struct QueueWeakSender<Q: MessageQueue> {
+    shared: Shared<Q>,
+}
+
+pub struct QueueReceiver<Q: MessageQueue> {
+    shared: Shared<Q>,
     queue_watcher_receiver: watch::Receiver<()>,
+    // the mutex only makes the receiver Sync, it is always accessed through &mut self
+    pending_change: Mutex<Option<PendingChange>>,
+}
+
+pub type Sender<T> = QueueSender<VecDeque<T>>;
+pub type Receiver<T> = QueueReceiver<VecDeque<T>>;
+pub type WeakSender<T> = QueueWeakSender<VecDeque<T>>;
+
+impl<T: Send> MessageQueue for VecDeque<T> {
+    type Input = T;
+    type Output = T;
+
+    fn push(&mut self, input: T) {
+        self.push_back(input);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+
+    fn clear(&mut self) {
+        VecDeque::clear(self);
+    }
 }
 
 pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    channel_with_queue(VecDeque::new())
+}
+
+pub fn channel_with_queue<Q: MessageQueue>(queue: Q) -> (QueueSender<Q>, QueueReceiver<Q>) {
     let (sender, receiver) = watch::channel(());
 
     let shared = Shared {
-        queue: arc_mutex_new(VecDeque::new()),
+        queue: arc_mutex_new(queue),
         sender_count: Arc::new(AtomicUsize::new(1)),
+        receiver_count: Arc::new(watch::Sender::new(1)),
         queue_watcher_sender: Arc::new(sender),
+        queue_condvar: Arc::new(Condvar::new()),
+        close_reason: arc_mutex_new(None),
     };
 
     (
-        Sender {
+        QueueSender {
             shared: shared.clone(),
         },
-        Receiver {
+        QueueReceiver {
             shared,
             queue_watcher_receiver: receiver,
+            pending_change: Mutex::new(None),
         },
     )
 }
 
-impl<T: Send> Sender<T> {
-    pub fn send(&self, msg: T) -> Result<(), SendError> {
-        if self.shared.queue_watcher_sender.receiver_count() != 0 {
-            self.shared.queue.lock().push_back(msg);
-            let _ = self.shared.queue_watcher_sender.send(());
+/// Resolves when the queue changes or when its next message becomes available.
+async fn wait_for_change(
+    mut queue_watcher_receiver: watch::Receiver<()>,
+    wakeup: Option<tokio::time::Instant>,
+) -> Result<(), watch::error::RecvError> {
+    match wakeup {
+        Some(wakeup) => timeout_at(wakeup, queue_watcher_receiver.changed())
+            .await
+            .unwrap_or(Ok(())),
+        None => queue_watcher_receiver.changed().await,
+    }
+}
 
-            Ok(())
+impl<Q: MessageQueue> Shared<Q> {
+    /// The error to report when nothing can be popped. Messages that are not available yet
+    /// keep the receivers connected. Closing takes precedence over dropping the senders, so that
+    /// the receivers see the reason.
+    fn empty_queue_error(&self, queue: &Q) -> Option<RecvError> {
+        if !queue.is_empty() {
+            None
+        } else if let Some(reason) = self.close_reason.lock().as_ref() {
+            Some(RecvError::Closed(reason.clone()))
+        } else if self.sender_count.load(atomic::Ordering::SeqCst) == 0 {
+            Some(RecvError::Disconnected)
         } else {
+            None
+        }
+    }
+
+    fn check_can_send(&self) -> Result<(), SendError> {
+        if let Some(reason) = self.close_reason.lock().as_ref() {
+            Err(SendError::Closed(reason.clone()))
+        } else if *self.receiver_count.borrow() == 0 {
             Err(SendError::Disconnected)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<Q: MessageQueue> QueueSender<Q> {
+    pub fn send(&self, msg: Q::Input) -> Result<(), SendError> {
+        self.shared.check_can_send()?;
+
+        let mut queue_guard = self.shared.queue.lock();
+        queue_guard.push(msg);
+        self.shared.queue_condvar.notify_one();
+        drop(queue_guard);
+
+        let _ = self.shared.queue_watcher_sender.send(());
+
+        Ok(())
+    }
+
+    /// Sends the messages in order while locking the queue only once.
+    pub fn send_batch(&self, msgs: impl IntoIterator<Item = Q::Input>) -> Result<(), SendError> {
+        self.shared.check_can_send()?;
+
+        // collected before locking, so that the iterator does not run inside the lock
+        let msgs: Vec<Q::Input> = msgs.into_iter().collect();
+        if !msgs.is_empty() {
+            let mut queue_guard = self.shared.queue.lock();
+            for msg in msgs {
+                queue_guard.push(msg);
+            }
+            self.shared.queue_condvar.notify_all();
+            drop(queue_guard);
+
+            let _ = self.shared.queue_watcher_sender.send(());
+        }
+
+        Ok(())
+    }
+
+    /// Closes the channel even if other senders are alive. The receivers get the messages that
+    /// are already in the queue, then [`RecvError::Closed`] with the given reason. Only the
+    /// first close has an effect.
+    pub fn close<Reason: Any + Send + Sync>(&self, reason: Reason) {
+        {
+            let mut close_reason = self.shared.close_reason.lock();
+            if close_reason.is_some() {
+                return;
+            }
+            *close_reason = Some(CloseReason::new(reason));
+        }
+
+        // see Drop
+        let queue_guard = self.shared.queue.lock();
+        self.shared.queue_condvar.notify_all();
+        drop(queue_guard);
+
+        let _ = self.shared.queue_watcher_sender.send(());
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.close_reason.lock().is_some()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count.load(atomic::Ordering::SeqCst)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        *self.shared.receiver_count.borrow()
+    }
+
+    /// Number of messages waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.lock().is_empty()
+    }
+
+    /// Resolves when every receiver is dropped.
+    pub async fn closed(&self) {
+        let mut receiver_count = self.shared.receiver_count.subscribe();
+        let _ = receiver_count.wait_for(|count| *count == 0).await;
+    }
+
+    /// Creates a sender that is not counted in [`QueueSender::sender_count`], so the receivers
+    /// get disconnected when the last sender is dropped even if weak senders are alive.
+    pub fn downgrade(&self) -> QueueWeakSender<Q> {
+        QueueWeakSender {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Puts back a message that was already received, even if the channel is closed.
+    pub(crate) fn send_back(&self, msg: Q::Input) {
+        let mut queue_guard = self.shared.queue.lock();
+        queue_guard.push(msg);
+        self.shared.queue_condvar.notify_one();
+        drop(queue_guard);
+
+        let _ = self.shared.queue_watcher_sender.send(());
+    }
+}
+
+impl<Q: MessageQueue> QueueWeakSender<Q> {
+    /// Returns None if every sender was already dropped. A disconnected channel is never
+    /// connected again.
+    pub fn upgrade(&self) -> Option<QueueSender<Q>> {
+        let mut sender_count = self.shared.sender_count.load(atomic::Ordering::SeqCst);
+        loop {
+            if sender_count == 0 {
+                return None;
+            }
+
+            match self.shared.sender_count.compare_exchange_weak(
+                sender_count,
+                sender_count + 1,
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    return Some(QueueSender {
+                        shared: self.shared.clone(),
+                    });
+                }
+                Err(current) => sender_count = current,
+            }
         }
     }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count.load(atomic::Ordering::SeqCst)
+    }
 }
 
-impl<T: Send> Receiver<T> {
-    pub async fn recv_async(&mut self) -> Result<T, RecvError> {
+impl<Q: MessageQueue> QueueReceiver<Q> {
+    pub async fn recv_async(&mut self) -> Result<Q::Output, RecvError> {
+        self.recv_async_with(|_, msg| msg).await
+    }
+
+    /// Same as [`QueueReceiver::recv_async`], but `take` is called with the message while the
+    /// queue is still locked, so the channel cannot be seen disconnected in between.
+    pub(crate) async fn recv_async_with<R>(
+        &mut self,
+        take: impl Fn(&Self, Q::Output) -> R,
+    ) -> Result<R, RecvError> {
+        loop {
+            // see poll_recv, the queue is checked first, so that a receiver that is already
+            // disconnected or closed does not wait for a change that never comes
+            let mut queue_watcher_receiver = self.queue_watcher_receiver.clone();
+            queue_watcher_receiver.mark_unchanged();
+
+            match self.try_recv_with(&take) {
+                Ok(msg) => break Ok(msg),
+                Err(TryRecvError::Disconnected) => break Err(RecvError::Disconnected),
+                Err(TryRecvError::Closed(reason)) => break Err(RecvError::Closed(reason)),
+                Err(TryRecvError::Empty) => (),
+            }
+
+            if wait_for_change(queue_watcher_receiver, self.next_wakeup())
+                .await
+                .is_err()
+            {
+                // unreachable!("This could not happen, since Self also holds a clone of the sender part");
+                break Err(RecvError::Disconnected);
+            }
+        }
+    }
+
+    /// Async counterpart of [`QueueReceiver::recv_timeout`].
+    pub async fn recv_async_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Q::Output, RecvTimeoutError> {
+        Ok(with_timeout(timeout, self.recv_async()).await??)
+    }
+
+    pub async fn recv_deadline(
+        &mut self,
+        deadline: tokio::time::Instant,
+    ) -> Result<Q::Output, RecvTimeoutError> {
+        Ok(with_deadline(deadline, self.recv_async()).await??)
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Q::Output, RecvError>> {
         loop {
-            if self.queue_watcher_receiver.changed().await.is_ok() {
+            if self.pending_change.get_mut().is_none() {
+                // the watcher has to be marked as unchanged before checking the queue, otherwise
+                // a message sent between the check and the poll would not wake this receiver up
+                let mut queue_watcher_receiver = self.queue_watcher_receiver.clone();
+                queue_watcher_receiver.mark_unchanged();
+
                 match self.try_pop() {
-                    Ok(msg) => break Ok(msg),
-                    Err(TryRecvError::Disconnected) => break Err(RecvError::Disconnected),
+                    Ok(msg) => break Poll::Ready(Ok(msg)),
+                    Err(TryRecvError::Disconnected) => {
+                        break Poll::Ready(Err(RecvError::Disconnected));
+                    }
+                    Err(TryRecvError::Closed(reason)) => {
+                        break Poll::Ready(Err(RecvError::Closed(reason)));
+                    }
                     Err(TryRecvError::Empty) => (),
                 }
-            } else {
-                // unreachable!("This could not happen, since Self also holds a clone of the sender part");
+
+                *self.pending_change.get_mut() = Some(Box::pin(wait_for_change(
+                    queue_watcher_receiver,
+                    self.next_wakeup(),
+                )));
+            }
+
+            let pending_change = self.pending_change.get_mut();
+            if let Some(change) = pending_change.as_mut() {
+                match change.as_mut().poll(cx) {
+                    Poll::Ready(Ok(())) => *pending_change = None,
+                    Poll::Ready(Err(_)) => {
+                        *pending_change = None;
+                        break Poll::Ready(Err(RecvError::Disconnected));
+                    }
+                    Poll::Pending => break Poll::Pending,
+                }
+            }
+        }
+    }
+
+    /// Takes every message that is in the queue, the result is empty if there is none.
+    pub fn pop_all(&self) -> Result<Vec<Q::Output>, RecvError> {
+        let mut msgs = Vec::new();
+        self.drain_into(&mut msgs)?;
+        Ok(msgs)
+    }
+
+    /// Appends every message that is in the queue to `buffer` and returns their number.
+    pub fn drain_into(&self, buffer: &mut Vec<Q::Output>) -> Result<usize, RecvError> {
+        self.pop_many(usize::MAX, buffer)
+    }
+
+    /// Waits for at least one message, then takes at most `max` of them.
+    pub async fn recv_many(&mut self, max: usize) -> Result<Vec<Q::Output>, RecvError> {
+        let mut msgs = Vec::new();
+        if max == 0 {
+            return Ok(msgs);
+        }
+
+        loop {
+            // see poll_recv
+            let mut queue_watcher_receiver = self.queue_watcher_receiver.clone();
+            queue_watcher_receiver.mark_unchanged();
+
+            if self.pop_many(max, &mut msgs)? != 0 {
+                break Ok(msgs);
+            }
+
+            if wait_for_change(queue_watcher_receiver, self.next_wakeup())
+                .await
+                .is_err()
+            {
                 break Err(RecvError::Disconnected);
             }
         }
     }
 
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        match self.queue_watcher_receiver.has_changed() {
-            Ok(true) => self.try_pop(),
-            Ok(false) => {
-                if self.shared.sender_count.load(atomic::Ordering::SeqCst) == 0 {
-                    Err(TryRecvError::Disconnected)
-                } else {
-                    Err(TryRecvError::Empty)
+    fn pop_many(&self, max: usize, buffer: &mut Vec<Q::Output>) -> Result<usize, RecvError> {
+        let mut queue_guard = self.shared.queue.lock();
+
+        let mut count = 0;
+        while count < max
+            && let Some(msg) = queue_guard.pop()
+        {
+            buffer.push(msg);
+            count += 1;
+        }
+
+        if count != 0 {
+            let _ = self.shared.queue_watcher_sender.send(());
+            Ok(count)
+        } else if let Some(error) = self.shared.empty_queue_error(&queue_guard) {
+            Err(error)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Blocks the current thread until a message arrives, it does not need a tokio runtime.
+    pub fn recv_blocking(&self) -> Result<Q::Output, RecvError> {
+        self.recv_blocking_with(|_, msg| msg)
+    }
+
+    /// See [`QueueReceiver::recv_async_with`].
+    pub(crate) fn recv_blocking_with<R>(
+        &self,
+        take: impl FnOnce(&Self, Q::Output) -> R,
+    ) -> Result<R, RecvError> {
+        let mut queue_guard = self.shared.queue.lock();
+        loop {
+            if let Some(msg) = queue_guard.pop() {
+                let received = take(self, msg);
+                let _ = self.shared.queue_watcher_sender.send(());
+                break Ok(received);
+            } else if let Some(error) = self.shared.empty_queue_error(&queue_guard) {
+                break Err(error);
+            }
+
+            match queue_guard.next_wakeup() {
+                Some(wakeup) => {
+                    self.shared
+                        .queue_condvar
+                        .wait_until(&mut queue_guard, wakeup.into_std());
                 }
+                None => self.shared.queue_condvar.wait(&mut queue_guard),
             }
-            Err(_) => {
-                // unreachable!("This could not happen, since Self also holds a clone of the sender part");
-                Err(TryRecvError::Disconnected)
+        }
+    }
+
+    /// Same as [`QueueReceiver::recv_blocking`], but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Q::Output, RecvTimeoutError> {
+        self.recv_timeout_with(timeout, |_, msg| msg)
+    }
+
+    /// See [`QueueReceiver::recv_async_with`].
+    pub(crate) fn recv_timeout_with<R>(
+        &self,
+        timeout: Duration,
+        take: impl FnOnce(&Self, Q::Output) -> R,
+    ) -> Result<R, RecvTimeoutError> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self
+                .recv_blocking_with(take)
+                .map_err(RecvTimeoutError::from);
+        };
+
+        let mut queue_guard = self.shared.queue.lock();
+        loop {
+            if let Some(msg) = queue_guard.pop() {
+                let received = take(self, msg);
+                let _ = self.shared.queue_watcher_sender.send(());
+                break Ok(received);
+            } else if let Some(error) = self.shared.empty_queue_error(&queue_guard) {
+                break Err(error.into());
+            } else if Instant::now() >= deadline {
+                break Err(RecvTimeoutError::Timeout);
             }
+
+            let wait_until = queue_guard
+                .next_wakeup()
+                .map_or(deadline, |wakeup| wakeup.into_std().min(deadline));
+            self.shared
+                .queue_condvar
+                .wait_until(&mut queue_guard, wait_until);
         }
     }
 
-    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+    pub fn try_recv(&self) -> Result<Q::Output, TryRecvError> {
+        self.try_pop()
+    }
+
+    pub fn try_pop(&self) -> Result<Q::Output, TryRecvError> {
+        self.try_recv_with(|_, msg| msg)
+    }
+
+    /// See [`QueueReceiver::recv_async_with`].
+    pub(crate) fn try_recv_with<R>(
+        &self,
+        take: impl FnOnce(&Self, Q::Output) -> R,
+    ) -> Result<R, TryRecvError> {
         let mut queue_guard = self.shared.queue.lock();
-        if let Some(msg) = queue_guard.pop_front() {
+        if let Some(msg) = queue_guard.pop() {
+            let received = take(self, msg);
             let _ = self.shared.queue_watcher_sender.send(());
-            Ok(msg)
-        } else if self.shared.sender_count.load(atomic::Ordering::SeqCst) == 0 {
-            Err(TryRecvError::Disconnected)
+            Ok(received)
+        } else if let Some(error) = self.shared.empty_queue_error(&queue_guard) {
+            Err(error.into())
         } else {
             Err(TryRecvError::Empty)
         }
     }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count.load(atomic::Ordering::SeqCst)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        *self.shared.receiver_count.borrow()
+    }
+
+    /// Number of messages waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.lock().is_empty()
+    }
+
+    /// Resolves when every sender is dropped, even if there are messages left in the queue.
+    pub async fn senders_gone(&self) {
+        loop {
+            // see recv_async
+            let mut queue_watcher_receiver = self.queue_watcher_receiver.clone();
+            queue_watcher_receiver.mark_unchanged();
+
+            if self.sender_count() == 0 || queue_watcher_receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn next_wakeup(&self) -> Option<tokio::time::Instant> {
+        self.shared.queue.lock().next_wakeup()
+    }
+
+    /// The sender keeps the receivers connected while it is alive, like any other sender. When
+    /// it is created for a received message, it has to be created in the `take` function of
+    /// [`QueueReceiver::recv_async_with`] or its siblings, otherwise another receiver can see the
+    /// channel disconnected before the sender exists.
+    pub(crate) fn create_sender(&self) -> QueueSender<Q> {
+        self.shared
+            .sender_count
+            .fetch_add(1, atomic::Ordering::SeqCst);
+        QueueSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(error: RecvError) -> Self {
+        match error {
+            RecvError::Disconnected => Self::Disconnected,
+            RecvError::Closed(reason) => Self::Closed(reason),
+        }
+    }
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(error: RecvError) -> Self {
+        match error {
+            RecvError::Disconnected => Self::Disconnected,
+            RecvError::Closed(reason) => Self::Closed(reason),
+        }
+    }
+}
+
+impl From<Timeout> for RecvTimeoutError {
+    fn from(_: Timeout) -> Self {
+        Self::Timeout
+    }
 }
 
-impl<T: Send> Clone for Shared<T> {
+impl<Q: MessageQueue> Clone for Shared<Q> {
     fn clone(&self) -> Self {
         Self {
             queue: self.queue.clone(),
             sender_count: self.sender_count.clone(),
+            receiver_count: self.receiver_count.clone(),
             queue_watcher_sender: self.queue_watcher_sender.clone(),
+            queue_condvar: self.queue_condvar.clone(),
+            close_reason: self.close_reason.clone(),
         }
     }
 }
 
-impl<T: Send> Clone for Sender<T> {
+impl<Q: MessageQueue> Clone for QueueSender<Q> {
     fn clone(&self) -> Self {
         self.shared
             .sender_count
@@ -146,29 +669,53 @@ impl<T: Send> Clone for Sender<T> {
     }
 }
 
-impl<T: Send> Drop for Sender<T> {
+impl<Q: MessageQueue> Clone for QueueWeakSender<Q> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Q: MessageQueue> Drop for QueueSender<Q> {
     fn drop(&mut self) {
         self.shared
             .sender_count
             .fetch_sub(1, atomic::Ordering::SeqCst);
 
+        // the lock makes sure that a blocked receiver is either already waiting on the condvar
+        // or it has not checked the sender count yet
+        let queue_guard = self.shared.queue.lock();
+        self.shared.queue_condvar.notify_all();
+        drop(queue_guard);
+
         let _ = self.shared.queue_watcher_sender.send(());
     }
 }
 
-impl<T: Send> Clone for Receiver<T> {
+impl<Q: MessageQueue> Clone for QueueReceiver<Q> {
     fn clone(&self) -> Self {
+        self.shared
+            .receiver_count
+            .send_modify(|receiver_count| *receiver_count += 1);
         Self {
             shared: self.shared.clone(),
             queue_watcher_receiver: self.queue_watcher_receiver.clone(),
+            pending_change: Mutex::new(None),
         }
     }
 }
 
-impl<T: Send> Drop for Receiver<T> {
+impl<Q: MessageQueue> Drop for QueueReceiver<Q> {
     fn drop(&mut self) {
-        // if this is the last receiver, then empty the queue
-        if self.shared.queue_watcher_sender.receiver_count() == 1 {
+        let mut remaining_receivers = 0;
+        self.shared.receiver_count.send_modify(|receiver_count| {
+            *receiver_count -= 1;
+            remaining_receivers = *receiver_count;
+        });
+
+        // if this was the last receiver, then empty the queue
+        if remaining_receivers == 0 {
             let mut queue_guard = self.shared.queue.lock();
             if !queue_guard.is_empty() {
                 queue_guard.clear();
@@ -178,15 +725,43 @@ impl<T: Send> Drop for Receiver<T> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
+#[cfg(feature = "futures")]
+impl<Q: MessageQueue> futures_core::Stream for QueueReceiver<Q> {
+    type Item = Q::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_recv(cx).map(Result::ok)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Q: MessageQueue> futures_sink::Sink<Q::Input> for QueueSender<Q> {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Q::Input) -> Result<(), Self::Error> {
+        self.send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
 
-    use parking_lot::Mutex;
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use crate::sync::types::{ArcMutex, arc_mutex_new};
 
-    use crate::sync::types::ArcMutex;
+    use std::time::Duration;
 
-    use super::{Receiver, channel};
+    use super::{Receiver, RecvError, RecvTimeoutError, SendError, TryRecvError, channel};
 
     #[derive(Debug, Clone, Copy, Eq, PartialEq)]
     struct Msg(usize);
@@ -198,7 +773,7 @@ mod tests {
     }
 
     async fn run_test(number_of_workers: usize) {
-        let received_values = Arc::new(Mutex::new(Vec::<Msg>::new()));
+        let received_values = arc_mutex_new(Vec::<Msg>::new());
 
         let (sender, receiver) = channel();
 
@@ -253,4 +828,361 @@ mod tests {
             run_test(10).await;
         }
     }
+
+    #[cfg(feature = "futures")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_and_sink() {
+        use futures::{StreamExt, stream};
+
+        let (sender, receiver) = channel::<Msg>();
+
+        let collectors: Vec<_> = (0..3)
+            .map(|_| tokio::spawn(receiver.clone().collect::<Vec<_>>()))
+            .collect();
+        drop(receiver);
+
+        stream::iter((0..100).map(Msg))
+            .map(Ok)
+            .forward(sender)
+            .await
+            .unwrap();
+
+        let mut received_values = Vec::new();
+        for collector in collectors {
+            received_values.extend(collector.await.unwrap());
+        }
+        received_values.sort_by_key(|msg| msg.0);
+
+        assert_eq!(received_values, (0..100).map(Msg).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn recv_blocking() {
+        let (sender, receiver) = channel::<Msg>();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || {
+                    let mut received_values = Vec::new();
+                    while let Ok(msg) = receiver.recv_blocking() {
+                        received_values.push(msg);
+                    }
+                    received_values
+                })
+            })
+            .collect();
+
+        for i in 0..100 {
+            sender.send(Msg(i)).unwrap();
+        }
+        drop(sender);
+
+        let mut received_values = Vec::new();
+        for thread in threads {
+            received_values.extend(thread.join().unwrap());
+        }
+        received_values.sort_by_key(|msg| msg.0);
+
+        assert_eq!(received_values, (0..100).map(Msg).collect::<Vec<_>>());
+        assert!(matches!(
+            receiver.recv_blocking(),
+            Err(RecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (sender, receiver) = channel::<Msg>();
+
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            sender.send(Msg(7)).unwrap();
+        });
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(2)).unwrap(),
+            Msg(7)
+        );
+
+        thread.join().unwrap();
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(2)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recv_deadline() {
+        let (sender, mut receiver) = channel::<Msg>();
+
+        assert!(matches!(
+            receiver.recv_async_timeout(Duration::from_secs(1)).await,
+            Err(RecvTimeoutError::Timeout)
+        ));
+
+        sender.send(Msg(7)).unwrap();
+        assert_eq!(
+            receiver
+                .recv_deadline(tokio::time::Instant::now() + Duration::from_secs(1))
+                .await
+                .unwrap(),
+            Msg(7)
+        );
+
+        // too large for a deadline, so it waits without one
+        sender.send(Msg(8)).unwrap();
+        assert_eq!(
+            receiver.recv_async_timeout(Duration::MAX).await.unwrap(),
+            Msg(8)
+        );
+
+        drop(sender);
+        assert!(matches!(
+            receiver.recv_async_timeout(Duration::from_secs(1)).await,
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn batches() {
+        let (sender, mut receiver) = channel::<Msg>();
+
+        sender.send_batch((0..5).map(Msg)).unwrap();
+
+        assert_eq!(receiver.recv_many(2).await.unwrap(), vec![Msg(0), Msg(1)]);
+        let mut buffer = vec![Msg(7)];
+        assert_eq!(receiver.drain_into(&mut buffer).unwrap(), 3);
+        assert_eq!(buffer, vec![Msg(7), Msg(2), Msg(3), Msg(4)]);
+        assert!(receiver.pop_all().unwrap().is_empty());
+
+        let waiter = tokio::spawn({
+            let mut receiver = receiver.clone();
+            async move { receiver.recv_many(10).await.unwrap() }
+        });
+        tokio::task::yield_now().await;
+        sender.send_batch([Msg(8), Msg(9)]).unwrap();
+        assert_eq!(waiter.await.unwrap(), vec![Msg(8), Msg(9)]);
+
+        drop(sender);
+        assert!(matches!(
+            receiver.recv_many(2).await,
+            Err(RecvError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let (sender, mut receiver) = channel::<Msg>();
+        let other_sender = sender.clone();
+
+        sender.send_batch([Msg(0), Msg(1)]).unwrap();
+        sender.close("upstream failed");
+        other_sender.close("ignored");
+
+        assert!(other_sender.is_closed());
+        assert!(matches!(
+            other_sender.send(Msg(2)),
+            Err(SendError::Closed(reason)) if reason.downcast_ref() == Some(&"upstream failed")
+        ));
+
+        assert_eq!(receiver.recv_async().await.unwrap(), Msg(0));
+        assert_eq!(receiver.recv_blocking().unwrap(), Msg(1));
+
+        for _ in 0..2 {
+            assert!(matches!(
+                receiver.recv_async().await,
+                Err(RecvError::Closed(reason)) if reason.is::<&str>()
+            ));
+        }
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Closed(_))));
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Closed(_))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn close_while_waiting() {
+        let (sender, receiver) = channel::<Msg>();
+
+        let task = tokio::spawn({
+            let mut receiver = receiver.clone();
+            async move { receiver.recv_async().await }
+        });
+        let thread = std::thread::spawn(move || receiver.recv_blocking());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sender.close(());
+
+        let result = tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(RecvError::Closed(_))));
+        assert!(matches!(thread.join().unwrap(), Err(RecvError::Closed(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn counts_and_lifecycle() {
+        let (sender, receiver) = channel::<Msg>();
+        let other_sender = sender.clone();
+        let other_receiver = receiver.clone();
+
+        assert_eq!(sender.sender_count(), 2);
+        assert_eq!(receiver.receiver_count(), 2);
+
+        sender.send_batch([Msg(0), Msg(1)]).unwrap();
+        assert_eq!(sender.len(), 2);
+        assert_eq!(other_receiver.try_recv().unwrap(), Msg(0));
+        assert_eq!(receiver.len(), 1);
+
+        let senders_gone = tokio::spawn({
+            let receiver = receiver.clone();
+            async move { receiver.senders_gone().await }
+        });
+        drop(other_sender);
+        drop(sender);
+        tokio::time::timeout(Duration::from_secs(2), senders_gone)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receiver.sender_count(), 0);
+
+        // the message sent before is still there
+        assert!(!receiver.is_empty());
+
+        drop(other_receiver);
+        assert_eq!(receiver.receiver_count(), 1);
+
+        let (sender, receiver) = channel::<Msg>();
+        let closed = tokio::spawn(async move { sender.closed().await });
+        drop(receiver);
+        tokio::time::timeout(Duration::from_secs(2), closed)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn weak_sender() {
+        let (sender, receiver) = channel::<Msg>();
+        let weak_sender = sender.downgrade();
+        assert_eq!(receiver.sender_count(), 1);
+
+        let upgraded = weak_sender.upgrade().unwrap();
+        assert_eq!(receiver.sender_count(), 2);
+        upgraded.send(Msg(0)).unwrap();
+        drop(upgraded);
+        drop(sender);
+
+        // the weak sender does not keep the receiver connected
+        assert_eq!(receiver.try_recv().unwrap(), Msg(0));
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+        assert!(weak_sender.upgrade().is_none());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::{future::block_on, thread};
+
+    use super::{RecvError, TryRecvError, channel};
+
+    #[test]
+    fn sender_drop_wakes_up_blocked_receiver() {
+        loom::model(|| {
+            let (sender, receiver) = channel::<usize>();
+
+            let sender_thread = thread::spawn(move || {
+                sender.send(0).unwrap();
+                drop(sender);
+            });
+
+            assert_eq!(receiver.recv_blocking().unwrap(), 0);
+            assert!(matches!(
+                receiver.recv_blocking(),
+                Err(RecvError::Disconnected)
+            ));
+            sender_thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn async_receiver_does_not_miss_a_message() {
+        loom::model(|| {
+            let (sender, mut receiver) = channel::<usize>();
+
+            let sender_thread = thread::spawn(move || sender.send(0).unwrap());
+
+            assert_eq!(block_on(receiver.recv_async()).unwrap(), 0);
+            sender_thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn racing_senders() {
+        // three threads are not explorable without bounding the preemptions
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(3);
+
+        builder.check(|| {
+            let (sender, receiver) = channel::<usize>();
+            let other_sender = sender.clone();
+
+            let sender_threads = [
+                thread::spawn(move || sender.send(0).unwrap()),
+                thread::spawn(move || other_sender.send(1).unwrap()),
+            ];
+
+            let mut received = [
+                receiver.recv_blocking().unwrap(),
+                receiver.recv_blocking().unwrap(),
+            ];
+            received.sort();
+            assert_eq!(received, [0, 1]);
+            assert!(matches!(
+                receiver.recv_blocking(),
+                Err(RecvError::Disconnected)
+            ));
+
+            for sender_thread in sender_threads {
+                sender_thread.join().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn weak_sender_upgrade_races_with_drop() {
+        loom::model(|| {
+            let (sender, receiver) = channel::<usize>();
+            let weak_sender = sender.downgrade();
+
+            let upgrading_thread = thread::spawn(move || {
+                if let Some(sender) = weak_sender.upgrade() {
+                    sender.send(0).unwrap();
+                }
+            });
+            drop(sender);
+            upgrading_thread.join().unwrap();
+
+            // a message sent by an upgraded sender is never lost
+            match receiver.try_recv() {
+                Ok(msg) => assert_eq!(msg, 0),
+                Err(error) => assert!(matches!(error, TryRecvError::Disconnected)),
+            }
+            assert!(matches!(
+                receiver.try_recv(),
+                Err(TryRecvError::Disconnected)
+            ));
+        });
+    }
 }
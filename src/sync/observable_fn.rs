@@ -109,7 +109,7 @@ impl<T> DerefMut for ObservableBorrower<'_, T> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
     use std::sync::Arc;
 
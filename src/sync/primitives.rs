@@ -0,0 +1,214 @@
+//! # Primitives
+//!
+//! The locks, condition variables and atomics used by the sync module. They are the parking_lot
+//! and std ones, except when the crate is built with `--cfg loom`, then they are replaced by the
+//! [loom](https://docs.rs/loom) models behind the same API, so the model tests can explore the
+//! interleavings of the threads:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib
+//! ```
+//!
+//! The regular tests are skipped in a loom build, since the loom primitives cannot be used
+//! outside of [`loom::model`]. The tokio primitives have no loom models, the futures waiting for
+//! them are driven by `loom::future::block_on`. Each call of [`Notify`] is a point where loom
+//! can switch threads, so a notification lost between checking a state and waiting for its
+//! change is found. The calls of `watch` are not such points, loom only switches threads at the
+//! locks around them. Loom does not model time, so a timed wait in a model can only end by a
+//! notification, or immediately if its deadline has already passed.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Arc, atomic};
+
+#[cfg(not(loom))]
+pub(crate) use tokio::sync::Notify;
+
+#[cfg(not(loom))]
+pub(crate) use parking_lot::{
+    Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{Arc, atomic};
+
+#[cfg(loom)]
+pub(crate) use self::loom_models::{
+    Condvar, Mutex, MutexGuard, Notify, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+#[cfg(loom)]
+mod loom_models {
+    use std::{
+        ops::{Deref, DerefMut},
+        sync::Arc,
+        time::Instant,
+    };
+
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use or_die::OrDie;
+    use tokio::sync::futures::{Notified, OwnedNotified};
+
+    pub struct Mutex<T>(loom::sync::Mutex<T>);
+
+    pub struct MutexGuard<'a, T> {
+        // only None while the guard is given to the condvar
+        inner: Option<loom::sync::MutexGuard<'a, T>>,
+    }
+
+    pub struct RwLock<T>(loom::sync::RwLock<T>);
+
+    pub type RwLockReadGuard<'a, T> = loom::sync::RwLockReadGuard<'a, T>;
+    pub type RwLockWriteGuard<'a, T> = loom::sync::RwLockWriteGuard<'a, T>;
+
+    pub struct Condvar(loom::sync::Condvar);
+
+    pub struct WaitTimeoutResult(bool);
+
+    pub struct Notify {
+        inner: Arc<tokio::sync::Notify>,
+        // touched by every call, so that loom can switch threads there
+        steps: AtomicUsize,
+    }
+
+    impl<T> Mutex<T> {
+        pub fn new(value: T) -> Self {
+            Self(loom::sync::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            MutexGuard {
+                inner: Some(self.0.lock().or_die()),
+            }
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().or_die()
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().or_die()
+        }
+    }
+
+    impl<T: Default> Default for Mutex<T> {
+        fn default() -> Self {
+            Self::new(T::default())
+        }
+    }
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &Self::Target {
+            self.inner.as_ref().or_die()
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            self.inner.as_mut().or_die()
+        }
+    }
+
+    impl<T> RwLock<T> {
+        pub fn new(value: T) -> Self {
+            Self(loom::sync::RwLock::new(value))
+        }
+
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().or_die()
+        }
+
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().or_die()
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().or_die()
+        }
+    }
+
+    impl<T: Default> Default for RwLock<T> {
+        fn default() -> Self {
+            Self::new(T::default())
+        }
+    }
+
+    impl Condvar {
+        pub fn new() -> Self {
+            Self(loom::sync::Condvar::new())
+        }
+
+        pub fn wait<T>(&self, guard: &mut MutexGuard<'_, T>) {
+            let inner = guard.inner.take().or_die();
+            guard.inner = Some(self.0.wait(inner).or_die());
+        }
+
+        pub fn wait_until<T>(
+            &self,
+            guard: &mut MutexGuard<'_, T>,
+            deadline: Instant,
+        ) -> WaitTimeoutResult {
+            if Instant::now() >= deadline {
+                WaitTimeoutResult(true)
+            } else {
+                self.wait(guard);
+                WaitTimeoutResult(false)
+            }
+        }
+
+        pub fn notify_one(&self) {
+            self.0.notify_one();
+        }
+
+        pub fn notify_all(&self) {
+            self.0.notify_all();
+        }
+    }
+
+    impl Default for Condvar {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl WaitTimeoutResult {
+        pub fn timed_out(&self) -> bool {
+            self.0
+        }
+    }
+
+    impl Notify {
+        pub fn new() -> Self {
+            Self {
+                inner: Arc::new(tokio::sync::Notify::new()),
+                steps: AtomicUsize::new(0),
+            }
+        }
+
+        pub fn notified(&self) -> Notified<'_> {
+            self.step();
+            self.inner.notified()
+        }
+
+        pub fn notified_owned(self: Arc<Self>) -> OwnedNotified {
+            self.step();
+            self.inner.clone().notified_owned()
+        }
+
+        pub fn notify_waiters(&self) {
+            self.step();
+            self.inner.notify_waiters();
+        }
+
+        fn step(&self) {
+            self.steps.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Default for Notify {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! # Priority Multi Producer, Collective Consumer
+//!
+//! An [`mpcc`](super::mpcc) channel where the messages with a higher priority are received
+//! first, the messages with the same priority are received in the order they were sent.
+//!
+//! With aging, a waiting message gains one priority level every time `aging_interval` messages
+//! are received, so a steady stream of urgent messages cannot starve the others.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use super::mpcc::{MessageQueue, QueueReceiver, QueueSender, SendError, channel_with_queue};
+
+pub type Priority = u32;
+
+pub type Sender<T> = QueueSender<PriorityQueue<T>>;
+pub type Receiver<T> = QueueReceiver<PriorityQueue<T>>;
+
+struct Entry<T> {
+    msg: T,
+    // the number of received messages when this one was sent
+    sent_at_pop: u64,
+}
+
+pub struct PriorityQueue<T> {
+    lanes: BTreeMap<Priority, VecDeque<Entry<T>>>,
+    aging_interval: Option<u64>,
+    pop_count: u64,
+    len: usize,
+}
+
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    channel_with_queue(PriorityQueue::new())
+}
+
+pub fn channel_with_aging<T: Send>(aging_interval: u64) -> (Sender<T>, Receiver<T>) {
+    channel_with_queue(PriorityQueue::with_aging(aging_interval))
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            lanes: BTreeMap::new(),
+            aging_interval: None,
+            pop_count: 0,
+            len: 0,
+        }
+    }
+
+    pub fn with_aging(aging_interval: u64) -> Self {
+        Self {
+            aging_interval: Some(aging_interval.max(1)),
+            ..Self::new()
+        }
+    }
+
+    fn effective_priority(&self, priority: Priority, entry: &Entry<T>) -> u64 {
+        let age = self.pop_count - entry.sent_at_pop;
+        let gained = self
+            .aging_interval
+            .map_or(0, |aging_interval| age / aging_interval);
+
+        u64::from(priority).saturating_add(gained)
+    }
+
+    fn lane_to_pop(&self) -> Option<Priority> {
+        let mut selected: Option<(Priority, u64)> = None;
+
+        // the front of a lane is its oldest message, so it has the highest effective priority;
+        // on a tie the lane with the higher base priority wins
+        for (priority, lane) in self.lanes.iter().rev() {
+            if let Some(entry) = lane.front() {
+                let effective_priority = self.effective_priority(*priority, entry);
+                if selected
+                    .is_none_or(|(_, selected_priority)| effective_priority > selected_priority)
+                {
+                    selected = Some((*priority, effective_priority));
+                }
+            }
+        }
+
+        selected.map(|(priority, _)| priority)
+    }
+}
+
+impl<T: Send> MessageQueue for PriorityQueue<T> {
+    type Input = (Priority, T);
+    type Output = T;
+
+    fn push(&mut self, (priority, msg): (Priority, T)) {
+        self.lanes.entry(priority).or_default().push_back(Entry {
+            msg,
+            sent_at_pop: self.pop_count,
+        });
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let priority = self.lane_to_pop()?;
+
+        let lane = self.lanes.get_mut(&priority)?;
+        let entry = lane.pop_front()?;
+        if lane.is_empty() {
+            self.lanes.remove(&priority);
+        }
+
+        self.pop_count += 1;
+        self.len -= 1;
+
+        Some(entry.msg)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.lanes.clear();
+        self.len = 0;
+    }
+}
+
+impl<T: Send> QueueSender<PriorityQueue<T>> {
+    pub fn send_with_priority(&self, priority: Priority, msg: T) -> Result<(), SendError> {
+        self.send((priority, msg))
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{channel, channel_with_aging};
+
+    #[test]
+    fn priority_order() {
+        let (sender, receiver) = channel::<&str>();
+
+        sender.send_with_priority(0, "bulk 0").unwrap();
+        sender.send_with_priority(0, "bulk 1").unwrap();
+        sender.send_with_priority(5, "urgent 0").unwrap();
+        sender.send_with_priority(1, "normal").unwrap();
+        sender.send_with_priority(5, "urgent 1").unwrap();
+
+        assert_eq!(
+            receiver.pop_all().unwrap(),
+            vec!["urgent 0", "urgent 1", "normal", "bulk 0", "bulk 1"]
+        );
+    }
+
+    #[test]
+    fn aging() {
+        let (sender, receiver) = channel_with_aging::<usize>(2);
+
+        sender.send_with_priority(0, 0).unwrap();
+
+        // a new urgent message arrives before every receive, the bulk one reaches priority 1
+        // after two received messages and it overtakes the fresh urgent ones after four
+        let received: Vec<_> = (1..7)
+            .map(|i| {
+                sender.send_with_priority(1, i).unwrap();
+                receiver.try_pop().unwrap()
+            })
+            .collect();
+        assert_eq!(received, vec![1, 2, 3, 4, 0, 5]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn received_exactly_once() {
+        let (sender, receiver) = channel::<usize>();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let mut receiver = receiver.clone();
+                tokio::spawn(async move {
+                    let mut received = Vec::new();
+                    while let Ok(msg) = receiver.recv_async().await {
+                        received.push(msg);
+                    }
+                    received
+                })
+            })
+            .collect();
+        drop(receiver);
+
+        for i in 0..1000 {
+            sender.send_with_priority((i % 3) as u32, i).unwrap();
+        }
+        drop(sender);
+
+        let mut received = Vec::new();
+        for worker in workers {
+            received.extend(worker.await.unwrap());
+        }
+        received.sort();
+
+        assert_eq!(received, (0..1000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,336 @@
+//! # Request/Response channel
+//!
+//! A [`Client`] sends a request and waits for its response, a [`Server`] receives the request
+//! together with a [`Responder`] that sends the response back. The requests are distributed
+//! between the servers like the messages of an [`mpcc`] channel.
+//!
+//! Dropping the future of a call cancels the request: a server skips it if it was not received
+//! yet, otherwise the server can check it with [`Responder::is_cancelled`].
+
+use std::{
+    any::Any,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use super::{
+    close_reason::CloseReason,
+    mpcc::{self, RecvError, RecvTimeoutError, SendError, TryRecvError},
+    timeout::{Timeout, with_deadline, with_timeout},
+};
+
+struct Request<Req, Resp> {
+    request: Req,
+    response_sender: oneshot::Sender<Resp>,
+}
+
+pub struct Client<Req: Send, Resp: Send> {
+    requests: mpcc::Sender<Request<Req, Resp>>,
+}
+
+pub struct Server<Req: Send, Resp: Send> {
+    requests: mpcc::Receiver<Request<Req, Resp>>,
+}
+
+/// Sends the response of a received request. Dropping it without responding makes the call
+/// return [`CallError::Unanswered`].
+pub struct Responder<Resp> {
+    response_sender: oneshot::Sender<Resp>,
+}
+
+#[derive(Debug)]
+pub enum CallError {
+    /// No server existed when the request was sent.
+    Disconnected,
+    Closed(CloseReason),
+    /// The responder was dropped without sending a response, or every server was dropped while
+    /// the request was still queued.
+    Unanswered,
+}
+
+#[derive(Debug)]
+pub enum CallTimeoutError {
+    Timeout,
+    Disconnected,
+    Closed(CloseReason),
+    Unanswered,
+}
+
+pub fn channel<Req: Send, Resp: Send>() -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let (sender, receiver) = mpcc::channel();
+    (Client { requests: sender }, Server { requests: receiver })
+}
+
+impl<Req: Send, Resp: Send> Client<Req, Resp> {
+    pub async fn call(&self, request: Req) -> Result<Resp, CallError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.requests.send(Request {
+            request,
+            response_sender,
+        })?;
+
+        // dropping the receiver cancels the request
+        response_receiver.await.map_err(|_| CallError::Unanswered)
+    }
+
+    /// The request is cancelled if the response does not arrive in time.
+    pub async fn call_timeout(
+        &self,
+        request: Req,
+        timeout: Duration,
+    ) -> Result<Resp, CallTimeoutError> {
+        Ok(with_timeout(timeout, self.call(request)).await??)
+    }
+
+    pub async fn call_deadline(
+        &self,
+        request: Req,
+        deadline: tokio::time::Instant,
+    ) -> Result<Resp, CallTimeoutError> {
+        Ok(with_deadline(deadline, self.call(request)).await??)
+    }
+
+    /// See [`mpcc::QueueSender::close`], the requests already sent are still served.
+    pub fn close<Reason: Any + Send + Sync>(&self, reason: Reason) {
+        self.requests.close(reason);
+    }
+
+    pub fn server_count(&self) -> usize {
+        self.requests.receiver_count()
+    }
+}
+
+impl<Req: Send, Resp: Send> Server<Req, Resp> {
+    pub async fn recv_async(&mut self) -> Result<(Req, Responder<Resp>), RecvError> {
+        loop {
+            if let Some(received) = Self::accept(self.requests.recv_async().await?) {
+                break Ok(received);
+            }
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<(Req, Responder<Resp>), TryRecvError> {
+        loop {
+            if let Some(received) = Self::accept(self.requests.try_recv()?) {
+                break Ok(received);
+            }
+        }
+    }
+
+    pub fn recv_blocking(&self) -> Result<(Req, Responder<Resp>), RecvError> {
+        loop {
+            if let Some(received) = Self::accept(self.requests.recv_blocking()?) {
+                break Ok(received);
+            }
+        }
+    }
+
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<(Req, Responder<Resp>), RecvTimeoutError> {
+        // without a deadline, mpcc also waits without one
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let remaining = deadline.map_or(timeout, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if let Some(received) = Self::accept(self.requests.recv_timeout(remaining)?) {
+                break Ok(received);
+            }
+        }
+    }
+
+    /// Returns None if the call was cancelled before the request was received.
+    fn accept(request: Request<Req, Resp>) -> Option<(Req, Responder<Resp>)> {
+        if request.response_sender.is_closed() {
+            None
+        } else {
+            Some((
+                request.request,
+                Responder {
+                    response_sender: request.response_sender,
+                },
+            ))
+        }
+    }
+}
+
+impl<Resp> Responder<Resp> {
+    /// Gives back the response if the call was cancelled.
+    pub fn respond(self, response: Resp) -> Result<(), Resp> {
+        self.response_sender.send(response)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.response_sender.is_closed()
+    }
+
+    /// Resolves when the caller drops the future of the call, e.g. because of a timeout.
+    pub async fn cancelled(&mut self) {
+        self.response_sender.closed().await;
+    }
+}
+
+impl From<SendError> for CallError {
+    fn from(value: SendError) -> Self {
+        match value {
+            SendError::Disconnected => Self::Disconnected,
+            SendError::Closed(reason) => Self::Closed(reason),
+        }
+    }
+}
+
+impl From<CallError> for CallTimeoutError {
+    fn from(value: CallError) -> Self {
+        match value {
+            CallError::Disconnected => Self::Disconnected,
+            CallError::Closed(reason) => Self::Closed(reason),
+            CallError::Unanswered => Self::Unanswered,
+        }
+    }
+}
+
+impl From<Timeout> for CallTimeoutError {
+    fn from(_: Timeout) -> Self {
+        Self::Timeout
+    }
+}
+
+impl<Req: Send, Resp: Send> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+impl<Req: Send, Resp: Send> Clone for Server<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CallError, CallTimeoutError, channel};
+    use crate::sync::mpcc::{RecvError, TryRecvError};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn call_multiple_servers() {
+        let (client, server) = channel::<usize, usize>();
+
+        let servers: Vec<_> = (0..3)
+            .map(|_| {
+                let mut server = server.clone();
+                tokio::spawn(async move {
+                    let mut served = 0;
+                    while let Ok((request, responder)) = server.recv_async().await {
+                        responder.respond(request * 2).unwrap();
+                        served += 1;
+                    }
+                    served
+                })
+            })
+            .collect();
+        drop(server);
+
+        let calls: Vec<_> = (0..100)
+            .map(|request| {
+                let client = client.clone();
+                tokio::spawn(async move { client.call(request).await.unwrap() })
+            })
+            .collect();
+        for (request, call) in calls.into_iter().enumerate() {
+            assert_eq!(call.await.unwrap(), request * 2);
+        }
+
+        drop(client);
+        let mut served = 0;
+        for server in servers {
+            served += server.await.unwrap();
+        }
+        assert_eq!(served, 100);
+    }
+
+    #[tokio::test]
+    async fn unanswered_and_disconnected() {
+        let (client, mut server) = channel::<usize, usize>();
+
+        let call = tokio::spawn({
+            let client = client.clone();
+            async move { client.call(0).await }
+        });
+        let (_, responder) = server.recv_async().await.unwrap();
+        drop(responder);
+        assert!(matches!(call.await.unwrap(), Err(CallError::Unanswered)));
+
+        drop(server);
+        assert!(matches!(client.call(1).await, Err(CallError::Disconnected)));
+    }
+
+    #[tokio::test]
+    async fn servers_dropped_while_the_call_is_pending() {
+        let (client, server) = channel::<usize, usize>();
+
+        let call = tokio::spawn(async move { client.call(0).await });
+        tokio::task::yield_now().await;
+
+        // the queued request is dropped with the last server
+        drop(server);
+        assert!(matches!(call.await.unwrap(), Err(CallError::Unanswered)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timeout_too_large_for_the_clock() {
+        let (client, server) = channel::<usize, usize>();
+
+        let server = std::thread::spawn(move || {
+            let (request, responder) = server.recv_timeout(Duration::MAX).unwrap();
+            responder.respond(request * 2).unwrap();
+        });
+
+        assert_eq!(client.call_timeout(7, Duration::MAX).await.unwrap(), 14);
+        server.join().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_cancels_the_request() {
+        let (client, server) = channel::<usize, usize>();
+
+        // not received before the timeout, so the server skips it
+        assert!(matches!(
+            client.call_timeout(0, Duration::from_millis(10)).await,
+            Err(CallTimeoutError::Timeout)
+        ));
+        assert!(matches!(server.try_recv(), Err(TryRecvError::Empty)));
+
+        let call = tokio::spawn({
+            let client = client.clone();
+            async move { client.call_timeout(1, Duration::from_millis(10)).await }
+        });
+        tokio::task::yield_now().await;
+
+        let (request, mut responder) = server.try_recv().unwrap();
+        assert_eq!(request, 1);
+        assert!(!responder.is_cancelled());
+
+        responder.cancelled().await;
+        assert!(matches!(
+            call.await.unwrap(),
+            Err(CallTimeoutError::Timeout)
+        ));
+        assert_eq!(responder.respond(1), Err(1));
+
+        drop(client);
+        assert!(matches!(
+            server.recv_blocking(),
+            Err(RecvError::Disconnected)
+        ));
+    }
+}
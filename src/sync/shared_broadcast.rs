@@ -0,0 +1,367 @@
+//! # Shared Broadcast
+//!
+//! Every message is stored only once in a ring shared by all receivers, each receiver keeps its
+//! own cursor into it and gets the messages as `Arc<T>`. A message is freed as soon as every
+//! receiver that existed at the time of sending has read it, so `T` does not need to be `Clone`.
+//!
+//! The ring has a fixed capacity. Sending into a full ring overwrites the oldest message, the
+//! receivers that have not read it yet lagged behind: their next pop returns
+//! [`RecvError::Lagged`] with the number of the lost messages, then they continue with the oldest
+//! message that is still in the ring.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use super::primitives::{
+    Mutex, Notify,
+    atomic::{self, AtomicUsize},
+};
+
+struct Slot<T> {
+    message: Arc<T>,
+    unread_by: usize,
+}
+
+struct Ring<T> {
+    slots: VecDeque<Slot<T>>,
+    capacity: usize,
+    first_sequence: u64,
+    receiver_count: usize,
+}
+
+struct Shared<T> {
+    ring: Mutex<Ring<T>>,
+    notify: Notify,
+    sender_count: AtomicUsize,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    cursor: Mutex<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    SenderDropped,
+    /// The given number of messages were overwritten before this receiver could read them.
+    Lagged(u64),
+}
+
+impl<T> Ring<T> {
+    fn end_sequence(&self) -> u64 {
+        self.first_sequence + self.slots.len() as u64
+    }
+
+    fn mark_as_read(&mut self, sequence: u64) {
+        let index = (sequence - self.first_sequence) as usize;
+        self.slots[index].unread_by -= 1;
+    }
+
+    fn release_read_slots(&mut self) {
+        while self.slots.front().is_some_and(|slot| slot.unread_by == 0) {
+            self.slots.pop_front();
+            self.first_sequence += 1;
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// At most `capacity` messages are kept for the slow receivers, it panics if it is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity != 0,
+            "the capacity of a shared broadcast must not be zero"
+        );
+
+        Self {
+            shared: Arc::new(Shared {
+                ring: Mutex::new(Ring {
+                    slots: VecDeque::with_capacity(capacity),
+                    capacity,
+                    first_sequence: 0,
+                    receiver_count: 0,
+                }),
+                notify: Notify::new(),
+                sender_count: AtomicUsize::new(1),
+            }),
+        }
+    }
+
+    pub fn send(&self, object: T) {
+        let mut ring = self.shared.ring.lock();
+        if ring.receiver_count != 0 {
+            if ring.slots.len() == ring.capacity {
+                // the receivers that have not read it yet find out from their cursors
+                ring.slots.pop_front();
+                ring.first_sequence += 1;
+            }
+
+            let unread_by = ring.receiver_count;
+            ring.slots.push_back(Slot {
+                message: Arc::new(object),
+                unread_by,
+            });
+            self.shared.notify.notify_waiters();
+        }
+    }
+
+    pub fn create_receiver(&self) -> Receiver<T> {
+        Receiver::new(self.shared.clone())
+    }
+
+    /// Number of messages that are not yet read by every receiver.
+    pub fn len(&self) -> usize {
+        self.shared.ring.lock().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.ring.lock().slots.is_empty()
+    }
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>) -> Self {
+        let mut ring = shared.ring.lock();
+        ring.receiver_count += 1;
+        let cursor = ring.end_sequence();
+        drop(ring);
+
+        Self {
+            shared,
+            cursor: Mutex::new(cursor),
+        }
+    }
+
+    /// Returns [`RecvError::Lagged`] once after messages were overwritten before this receiver
+    /// could read them, the next call returns the oldest message that is still in the ring.
+    pub fn try_pop(&self) -> Result<Option<Arc<T>>, RecvError> {
+        let mut ring = self.shared.ring.lock();
+        let mut cursor = self.cursor.lock();
+
+        if *cursor < ring.first_sequence {
+            let missed = ring.first_sequence - *cursor;
+            *cursor = ring.first_sequence;
+
+            Err(RecvError::Lagged(missed))
+        } else if *cursor < ring.end_sequence() {
+            let index = (*cursor - ring.first_sequence) as usize;
+            let message = ring.slots[index].message.clone();
+
+            ring.mark_as_read(*cursor);
+            ring.release_read_slots();
+            *cursor += 1;
+
+            Ok(Some(message))
+        } else if self.shared.sender_count.load(atomic::Ordering::SeqCst) == 0 {
+            Err(RecvError::SenderDropped)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn pop(&self) -> Result<Arc<T>, RecvError> {
+        loop {
+            // the notified future has to exist before checking the ring, otherwise a message
+            // sent between the check and the await would not wake this receiver up
+            let notified = self.shared.notify.notified();
+
+            if let Some(object) = self.try_pop()? {
+                break Ok(object);
+            }
+
+            notified.await;
+        }
+    }
+
+    pub fn create_receiver(&self) -> Receiver<T> {
+        Receiver::new(self.shared.clone())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared
+            .sender_count
+            .fetch_add(1, atomic::Ordering::SeqCst);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let ring = self.shared.ring.lock();
+        if self
+            .shared
+            .sender_count
+            .fetch_sub(1, atomic::Ordering::SeqCst)
+            == 1
+        {
+            self.shared.notify.notify_waiters();
+        }
+        drop(ring);
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.create_receiver()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut ring = self.shared.ring.lock();
+        ring.receiver_count -= 1;
+
+        // the unread messages do not wait for this receiver anymore, the ones that were
+        // overwritten are not in the ring anymore
+        let cursor = (*self.cursor.lock()).max(ring.first_sequence);
+        for sequence in cursor..ring.end_sequence() {
+            ring.mark_as_read(sequence);
+        }
+        ring.release_read_slots();
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct NotClone(usize);
+
+    #[tokio::test]
+    async fn send() {
+        let sender = Sender::<NotClone>::new(8);
+
+        let receiver0 = sender.create_receiver();
+        let receiver1 = sender.create_receiver();
+
+        sender.send(NotClone(0));
+        sender.send(NotClone(1));
+        {
+            let sender = sender.clone();
+            sender.send(NotClone(2));
+        }
+
+        let message0 = receiver0.try_pop().unwrap().unwrap();
+        assert_eq!(*message0, NotClone(0));
+        assert_eq!(*receiver0.try_pop().unwrap().unwrap(), NotClone(1));
+        assert_eq!(*receiver0.try_pop().unwrap().unwrap(), NotClone(2));
+        assert_eq!(receiver0.try_pop().unwrap(), None);
+
+        // the messages are not copied for the receivers
+        let message1 = receiver1.pop().await.unwrap();
+        assert!(Arc::ptr_eq(&message0, &message1));
+        assert_eq!(*receiver1.pop().await.unwrap(), NotClone(1));
+        assert_eq!(*receiver1.pop().await.unwrap(), NotClone(2));
+        assert_eq!(receiver1.try_pop().unwrap(), None);
+    }
+
+    #[test]
+    fn release_read_messages() {
+        let sender = Sender::<usize>::new(8);
+
+        // there is no receiver, so the message can be dropped right away
+        sender.send(0);
+        assert!(sender.is_empty());
+
+        let receiver0 = sender.create_receiver();
+        let receiver1 = sender.create_receiver();
+
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(sender.len(), 2);
+
+        assert_eq!(*receiver0.try_pop().unwrap().unwrap(), 1);
+        assert_eq!(sender.len(), 2);
+
+        assert_eq!(*receiver1.try_pop().unwrap().unwrap(), 1);
+        assert_eq!(sender.len(), 1);
+
+        let receiver2 = receiver1.create_receiver();
+        sender.send(3);
+        assert_eq!(sender.len(), 2);
+
+        drop(receiver0);
+        drop(receiver1);
+        assert_eq!(sender.len(), 1);
+
+        assert_eq!(*receiver2.try_pop().unwrap().unwrap(), 3);
+        assert!(sender.is_empty());
+    }
+
+    #[test]
+    fn overwrite_the_oldest_message() {
+        let sender = Sender::<usize>::new(2);
+        let slow_receiver = sender.create_receiver();
+        let fast_receiver = sender.create_receiver();
+
+        for message in 0..5 {
+            sender.send(message);
+            assert_eq!(*fast_receiver.try_pop().unwrap().unwrap(), message);
+        }
+        assert_eq!(sender.len(), 2);
+
+        assert_eq!(slow_receiver.try_pop(), Err(RecvError::Lagged(3)));
+        assert_eq!(*slow_receiver.try_pop().unwrap().unwrap(), 3);
+        assert_eq!(*slow_receiver.try_pop().unwrap().unwrap(), 4);
+        assert_eq!(slow_receiver.try_pop().unwrap(), None);
+        assert!(sender.is_empty());
+
+        // a lagging receiver releases the messages that are still in the ring when it is dropped
+        drop(fast_receiver);
+        for message in 5..8 {
+            sender.send(message);
+        }
+        drop(slow_receiver);
+        assert!(sender.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_sender() {
+        let (receiver0, receiver1) = {
+            let sender = Sender::<usize>::new(8);
+            let ret = (sender.create_receiver(), sender.create_receiver());
+
+            sender.send(7);
+
+            ret
+        };
+
+        assert_eq!(*receiver0.try_pop().unwrap().unwrap(), 7);
+        assert_eq!(*receiver1.pop().await.unwrap(), 7);
+
+        assert_eq!(receiver0.pop().await, Err(RecvError::SenderDropped));
+        assert_eq!(receiver1.try_pop(), Err(RecvError::SenderDropped));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drop_sender_while_waiting() {
+        let sender = Sender::<usize>::new(8);
+        let receiver = sender.create_receiver();
+
+        let task = tokio::spawn(async move { receiver.pop().await });
+
+        sleep(Duration::from_millis(100)).await;
+        drop(sender);
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(2)) => {
+                panic!("timeout waiting for the receiver");
+            }
+            result = task => {
+                assert_eq!(result.unwrap(), Err(RecvError::SenderDropped));
+            }
+        }
+    }
+}
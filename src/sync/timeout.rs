@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use tokio::time::{Instant, error::Elapsed, timeout_at};
+
+/// Returned when a receive operation does not complete before its deadline.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Timeout;
+
+impl From<Elapsed> for Timeout {
+    fn from(_: Elapsed) -> Self {
+        Self
+    }
+}
+
+pub(crate) async fn with_deadline<F: Future>(
+    deadline: Instant,
+    future: F,
+) -> Result<F::Output, Timeout> {
+    Ok(timeout_at(deadline, future).await?)
+}
+
+/// Like [`tokio::time::timeout`], a timeout that is too large for a deadline waits forever.
+pub(crate) async fn with_timeout<F: Future>(
+    timeout: Duration,
+    future: F,
+) -> Result<F::Output, Timeout> {
+    match Instant::now().checked_add(timeout) {
+        Some(deadline) => with_deadline(deadline, future).await,
+        None => Ok(future.await),
+    }
+}
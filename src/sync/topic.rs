@@ -0,0 +1,112 @@
+//! # Topics
+//!
+//! Hierarchical topics are `/` separated levels, e.g. `entity/42/position`. A [`TopicPattern`]
+//! can match a single level with `+` (`entity/+/position`) and all the remaining levels with a
+//! trailing `#` (`entity/42/#`, which also matches `entity/42`). A pattern with `#` in any other
+//! level is rejected.
+
+/// Implemented by messages that can be routed by topic, see
+/// [`Sender::create_topic_receiver`](super::broadcast::Sender::create_topic_receiver).
+pub trait Topic {
+    fn topic(&self) -> &str;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPattern {
+    levels: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TopicPatternError {
+    /// `#` matches all the remaining levels, so it can only be the last level.
+    MultiLevelWildcardNotLast,
+}
+
+impl TopicPattern {
+    pub fn new(pattern: &str) -> Result<Self, TopicPatternError> {
+        let levels: Vec<String> = pattern.split('/').map(str::to_string).collect();
+
+        if levels
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|level| level.as_str() == "#")
+        {
+            return Err(TopicPatternError::MultiLevelWildcardNotLast);
+        }
+
+        Ok(Self { levels })
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        let mut topic_levels = topic.split('/');
+
+        for level in &self.levels {
+            match (level.as_str(), topic_levels.next()) {
+                ("#", _) => return true,
+                (_, None) => return false,
+                ("+", Some(_)) => (),
+                (level, Some(topic_level)) => {
+                    if level != topic_level {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        topic_levels.next().is_none()
+    }
+}
+
+impl TryFrom<&str> for TopicPattern {
+    type Error = TopicPatternError;
+
+    fn try_from(pattern: &str) -> Result<Self, Self::Error> {
+        Self::new(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TopicPattern, TopicPatternError};
+
+    #[test]
+    fn matches() {
+        let pattern = TopicPattern::new("entity/42/position").unwrap();
+        assert!(pattern.matches("entity/42/position"));
+        assert!(!pattern.matches("entity/42"));
+        assert!(!pattern.matches("entity/42/position/x"));
+        assert!(!pattern.matches("entity/7/position"));
+
+        let pattern = TopicPattern::new("entity/+/position").unwrap();
+        assert!(pattern.matches("entity/42/position"));
+        assert!(pattern.matches("entity/7/position"));
+        assert!(!pattern.matches("entity/7/velocity"));
+        assert!(!pattern.matches("entity/position"));
+
+        let pattern = TopicPattern::new("entity/42/#").unwrap();
+        assert!(pattern.matches("entity/42"));
+        assert!(pattern.matches("entity/42/position"));
+        assert!(pattern.matches("entity/42/position/x"));
+        assert!(!pattern.matches("entity/7/position"));
+
+        assert!(TopicPattern::new("#").unwrap().matches("anything/at/all"));
+    }
+
+    #[test]
+    fn multi_level_wildcard_is_the_last_level() {
+        assert_eq!(
+            TopicPattern::new("entity/#/position"),
+            Err(TopicPatternError::MultiLevelWildcardNotLast)
+        );
+        assert_eq!(
+            TopicPattern::try_from("#/position"),
+            Err(TopicPatternError::MultiLevelWildcardNotLast)
+        );
+
+        // only a whole level is a wildcard
+        let pattern = TopicPattern::new("entity/a#b/position").unwrap();
+        assert!(pattern.matches("entity/a#b/position"));
+        assert!(!pattern.matches("entity/1/position"));
+    }
+}
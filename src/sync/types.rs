@@ -1,6 +1,6 @@
 use std::{rc::Rc, sync::Arc};
 
-use parking_lot::{
+use super::primitives::{
     Mutex, MutexGuard as PLMutexGuard, RwLock, RwLockReadGuard as PLRwLockReadGuard,
     RwLockWriteGuard as PLRwLockWriteGuard,
 };
@@ -1,10 +1,13 @@
-use std::sync::{Arc, Weak};
+use super::primitives::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
 
-#[derive(Clone)]
-pub struct UsageCounter(Arc<()>);
+// the usages are counted explicitly instead of by Arc's strong count, since loom has no Weak
+pub struct UsageCounter(Arc<AtomicUsize>);
 
 #[derive(Clone)]
-pub struct UsageCounterWatcher(Weak<()>);
+pub struct UsageCounterWatcher(Arc<AtomicUsize>);
 
 impl Default for UsageCounter {
     fn default() -> Self {
@@ -14,37 +17,70 @@ impl Default for UsageCounter {
 
 impl UsageCounter {
     pub fn new() -> Self {
-        Self(Arc::new(()))
+        Self(Arc::new(AtomicUsize::new(1)))
     }
 
     pub fn is_this_the_last(&self) -> bool {
-        Arc::strong_count(&self.0) == 1
+        self.0.load(Ordering::SeqCst) == 1
     }
 
     pub fn number_of_usages(&self) -> usize {
-        Arc::strong_count(&self.0)
+        self.0.load(Ordering::SeqCst)
     }
 
     pub fn watcher(&self) -> UsageCounterWatcher {
-        UsageCounterWatcher(Arc::downgrade(&self.0))
+        UsageCounterWatcher(self.0.clone())
     }
 }
 
 impl UsageCounterWatcher {
     pub fn is_observed_the_last(&self) -> bool {
-        Weak::strong_count(&self.0) == 1
+        self.0.load(Ordering::SeqCst) == 1
     }
 
     pub fn is_observed_dropped(&self) -> bool {
-        Weak::strong_count(&self.0) == 0
+        self.0.load(Ordering::SeqCst) == 0
     }
 
     pub fn number_of_usages(&self) -> usize {
-        Weak::strong_count(&self.0)
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns None if every usage counter was already dropped.
+    pub fn upgrade(&self) -> Option<UsageCounter> {
+        let mut number_of_usages = self.0.load(Ordering::SeqCst);
+        loop {
+            if number_of_usages == 0 {
+                return None;
+            }
+
+            match self.0.compare_exchange_weak(
+                number_of_usages,
+                number_of_usages + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Some(UsageCounter(self.0.clone())),
+                Err(current) => number_of_usages = current,
+            }
+        }
+    }
+}
+
+impl Clone for UsageCounter {
+    fn clone(&self) -> Self {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for UsageCounter {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
     use super::UsageCounter;
 
@@ -102,4 +138,44 @@ mod tests {
         assert!(!watcher1.is_observed_the_last());
         assert!(watcher1.is_observed_dropped());
     }
+
+    #[test]
+    fn upgrade_watcher() {
+        let usage_counter = UsageCounter::new();
+        let watcher = usage_counter.watcher();
+
+        let upgraded = watcher.upgrade().unwrap();
+        assert_eq!(usage_counter.number_of_usages(), 2);
+
+        drop(upgraded);
+        drop(usage_counter);
+        assert!(watcher.upgrade().is_none());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::thread;
+
+    use super::UsageCounter;
+
+    #[test]
+    fn upgrade_races_with_drop() {
+        loom::model(|| {
+            let usage_counter = UsageCounter::new();
+            let watcher = usage_counter.watcher();
+
+            let dropping_thread = thread::spawn(move || drop(usage_counter));
+
+            // an upgraded counter keeps the usage alive until it is dropped
+            if let Some(upgraded) = watcher.upgrade() {
+                assert!(!watcher.is_observed_dropped());
+                drop(upgraded);
+            }
+
+            dropping_thread.join().unwrap();
+            assert!(watcher.is_observed_dropped());
+            assert!(watcher.upgrade().is_none());
+        });
+    }
 }
@@ -0,0 +1,238 @@
+//! # Worker Pool
+//!
+//! Runs an async handler on the jobs of an [`mpcc`] channel with a given number of workers and
+//! sends the outputs to a results channel. The workers stop when the jobs channel is disconnected
+//! or closed, when the [`AppLoopState`] is stopped, or when the pool shrinks. A stopping worker
+//! always finishes the job it is handling.
+
+use std::{pin::Pin, sync::Arc};
+
+use tokio::task::JoinHandle;
+
+use super::{
+    app_loop_state::{AppLoopState, AppLoopStateWatcher},
+    mpcc,
+};
+
+type Handler<Job, Output> =
+    Arc<dyn Fn(Job) -> Pin<Box<dyn Future<Output = Output> + Send>> + Send + Sync>;
+
+struct Worker {
+    state: AppLoopState,
+    handle: JoinHandle<()>,
+}
+
+pub struct WorkerPool<Job: Send, Output: Send> {
+    jobs: mpcc::Receiver<Job>,
+    results: mpcc::Sender<Output>,
+    handler: Handler<Job, Output>,
+    app_loop_state: AppLoopState,
+    workers: Vec<Worker>,
+    stopping_workers: Vec<JoinHandle<()>>,
+}
+
+impl<Job, Output> WorkerPool<Job, Output>
+where
+    Job: Send + 'static,
+    Output: Send + 'static,
+{
+    /// Spawns `concurrency` workers on the current tokio runtime.
+    pub fn new<F, Fut>(
+        jobs: mpcc::Receiver<Job>,
+        concurrency: usize,
+        app_loop_state: &AppLoopState,
+        handler: F,
+    ) -> (Self, mpcc::Receiver<Output>)
+    where
+        F: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Output> + Send + 'static,
+    {
+        let (results, results_receiver) = mpcc::channel();
+
+        let mut pool = Self {
+            jobs,
+            results,
+            handler: Arc::new(move |job| Box::pin(handler(job))),
+            app_loop_state: app_loop_state.clone(),
+            workers: Vec::new(),
+            stopping_workers: Vec::new(),
+        };
+        pool.resize(concurrency);
+
+        (pool, results_receiver)
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Spawns new workers or stops the last ones, the stopped ones finish their current job.
+    pub fn resize(&mut self, concurrency: usize) {
+        while self.workers.len() < concurrency {
+            let state = AppLoopState::new();
+            // the watchers are created before the worker runs, so a stop right after spawning
+            // it is not missed
+            let handle = tokio::spawn(run_worker(
+                self.jobs.clone(),
+                self.results.clone(),
+                self.handler.clone(),
+                self.app_loop_state.watcher(),
+                state.watcher(),
+            ));
+
+            self.workers.push(Worker { state, handle });
+        }
+
+        while self.workers.len() > concurrency {
+            if let Some(worker) = self.workers.pop() {
+                worker.state.stop_loop();
+                self.stopping_workers.push(worker.handle);
+            }
+        }
+
+        self.stopping_workers.retain(|handle| !handle.is_finished());
+    }
+
+    /// Stops every worker and waits until they finish their current job.
+    pub async fn shutdown(mut self) {
+        self.resize(0);
+        self.join().await;
+    }
+
+    /// Waits until every worker stops, e.g. because the jobs channel is disconnected.
+    pub async fn join(mut self) {
+        let handles = self
+            .workers
+            .drain(..)
+            .map(|worker| worker.handle)
+            .chain(self.stopping_workers.drain(..));
+
+        for handle in handles {
+            if let Err(error) = handle.await
+                && error.is_panic()
+            {
+                std::panic::resume_unwind(error.into_panic());
+            }
+        }
+    }
+}
+
+async fn run_worker<Job: Send, Output: Send>(
+    mut jobs: mpcc::Receiver<Job>,
+    results: mpcc::Sender<Output>,
+    handler: Handler<Job, Output>,
+    app_loop_watcher: AppLoopStateWatcher,
+    worker_watcher: AppLoopStateWatcher,
+) {
+    loop {
+        // receiving is cancel safe, a job is never lost when the loop stops
+        let job = tokio::select! {
+            biased;
+            _ = app_loop_watcher.wait_for_quit() => break,
+            _ = worker_watcher.wait_for_quit() => break,
+            job = jobs.recv_async() => match job {
+                Ok(job) => job,
+                Err(_) => break,
+            },
+        };
+
+        let output = handler(job).await;
+
+        // nobody listens to the results if their receiver is dropped
+        let _ = results.send(output);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::time::Duration;
+
+    use super::WorkerPool;
+    use crate::sync::{app_loop_state::AppLoopState, mpcc};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn process_jobs() {
+        let (jobs, jobs_receiver) = mpcc::channel::<usize>();
+        let (pool, results) =
+            WorkerPool::new(jobs_receiver, 4, &AppLoopState::new(), |job| async move {
+                job * 2
+            });
+        assert_eq!(pool.concurrency(), 4);
+
+        jobs.send_batch(0..100).unwrap();
+        drop(jobs);
+
+        pool.join().await;
+
+        let mut outputs = results.pop_all().unwrap();
+        outputs.sort();
+        assert_eq!(outputs, (0..100).map(|job| job * 2).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_drains_in_flight_jobs() {
+        let (jobs, jobs_receiver) = mpcc::channel::<usize>();
+        let (pool, mut results) =
+            WorkerPool::new(jobs_receiver, 2, &AppLoopState::new(), |job| async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                job
+            });
+
+        // the third job is not started before the shutdown
+        jobs.send_batch([0, 1, 2]).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        pool.shutdown().await;
+
+        let mut outputs = results.pop_all().unwrap();
+        outputs.sort();
+        assert_eq!(outputs, vec![0, 1]);
+        assert!(matches!(
+            results.recv_async().await,
+            Err(mpcc::RecvError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn shutdown_right_after_new() {
+        let (_jobs, jobs_receiver) = mpcc::channel::<usize>();
+        let (mut pool, _results) = WorkerPool::new(
+            jobs_receiver,
+            2,
+            &AppLoopState::new(),
+            |job| async move { job },
+        );
+
+        // the workers have not run yet on this thread when they are stopped
+        pool.resize(3);
+        pool.resize(2);
+        tokio::time::timeout(Duration::from_secs(2), pool.shutdown())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resize_and_stop_loop() {
+        let app_loop_state = AppLoopState::new();
+        let (jobs, jobs_receiver) = mpcc::channel::<usize>();
+        let (mut pool, mut results) =
+            WorkerPool::new(jobs_receiver, 1, &app_loop_state, |job| async move { job });
+
+        pool.resize(3);
+        assert_eq!(pool.concurrency(), 3);
+        pool.resize(2);
+        assert_eq!(pool.concurrency(), 2);
+
+        jobs.send(7).unwrap();
+        let output = tokio::time::timeout(Duration::from_secs(2), results.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(output, 7);
+
+        app_loop_state.stop_loop();
+        tokio::time::timeout(Duration::from_secs(2), pool.join())
+            .await
+            .unwrap();
+    }
+}